@@ -0,0 +1,136 @@
+use std::rc::Rc;
+
+// Words are kept as raw source text, quotes included, and are only expanded
+// when the command runs.
+
+#[derive(Debug, Clone)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub background: bool, // terminated by &
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And, // &&
+    Or,  // ||
+}
+
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+    FunctionDef(String, Rc<Command>), // the body is a compound command
+}
+
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<String>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CompoundCommand {
+    BraceGroup(List),
+    Subshell(List),
+    If(IfClause),
+    While(WhileClause),
+    For(ForClause),
+    Case(CaseClause),
+    Conditional(CondExpr), // [[ ... ]]
+}
+
+#[derive(Debug, Clone)]
+pub enum CondExpr {
+    Word(String),                   // true when not empty
+    Unary(String, String),          // operator and operand, as in -f file
+    Binary(String, String, String), // left operand, operator, right operand
+    Not(Box<CondExpr>),
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+    Group(Box<CondExpr>), // ( ... )
+}
+
+#[derive(Debug, Clone)]
+pub struct IfClause {
+    pub branches: Vec<(List, List)>, // if/elif condition and its body
+    pub otherwise: Option<List>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WhileClause {
+    pub until: bool, // loop while the condition fails
+    pub condition: List,
+    pub body: List,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForClause {
+    pub name: String,
+    pub words: Option<Vec<String>>, // None loops over the positional parameters
+    pub body: List,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator {
+    Break,       // ;; stops at the first match
+    FallThrough, // ;& runs the next body without testing its patterns
+    Continue,    // ;;& keeps testing the next patterns
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub patterns: Vec<String>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseClause {
+    pub word: String,
+    pub items: Vec<CaseItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    Read,         // <
+    Write,        // >
+    Clobber,      // >|
+    Append,       // >>
+    ReadWrite,    // <>
+    DupRead,      // <&
+    DupWrite,     // >&
+    HereString,   // <<<
+    HereDocument, // << and <<-, whose target is the delimiter
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub fd: i32,
+    pub op: RedirectOp,
+    pub target: String,
+    pub document: Option<String>, // the text of a here-document
+}
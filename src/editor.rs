@@ -0,0 +1,1219 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::Ordering;
+
+use crate::exec::execute_script;
+use crate::prompt::printable;
+use crate::{read_line_fd, Shell, INTERRUPTED};
+
+// The editor of the lines typed at the prompt. While a line is read, the
+// terminal is put in raw mode so that each key is handled as it is typed,
+// with the Emacs bindings of readline, or its vi bindings after `set -o vi`.
+// The whole line is drawn again after every change: the prompt may take
+// several lines and the input wraps across the rows of the terminal.
+
+// How long to wait for the rest of an escape sequence, in milliseconds.
+const ESCAPE_TIMEOUT: i32 = 100;
+
+// The state kept from one line to the next.
+#[derive(Default)]
+pub struct Editor {
+    kill: String,                      // the text killed last, for Ctrl-Y and p
+    pending: VecDeque<Key>,            // keys to handle before reading more
+    recording: Option<Vec<Key>>,       // the keys of the vi command being typed
+    last_change: Vec<Key>, // the keys of the last vi command that changed the line, for .
+    char_search: Option<(char, char)>, // the last f, F, t or T and its character, for ; and ,
+    search: Option<(String, bool)>, // the last pattern searched for with / or ?, and whether backward
+    last_query: String,             // the text searched for last with Ctrl-R or Ctrl-S
+}
+
+#[derive(Clone, PartialEq)]
+enum Key {
+    Char(char), // control characters included
+    Meta(char), // a character typed with Alt, or after Escape
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    Delete,
+    Unknown,
+}
+
+// What the line becomes after a key.
+enum Outcome {
+    Edit,
+    Accept,
+    Visual, // edit the line with $VISUAL or $EDITOR
+    Eof,
+    Interrupt,
+}
+
+// Where the cursor is, counted from the first row of the prompt. The cursor
+// is said to be wrapped when it went to a new row because the one before got
+// full: the terminal keeps it at the end of that row until something else is
+// written.
+#[derive(Clone, Copy, Default)]
+struct Position {
+    row: usize,
+    column: usize,
+    wrapped: bool,
+}
+
+#[derive(Default)]
+struct Line<'a> {
+    prompt: String,
+    buffer: Vec<char>,
+    cursor: usize,
+    row: usize,                            // row of the cursor as last drawn
+    history: &'a [String],                 // the commands entered before, oldest first
+    entry: usize,                          // the entry shown, or one past the last for a new line
+    saved: Vec<char>,                      // the new line, while the history is shown
+    insert: bool,                          // in the insert mode of vi
+    undo: Vec<(Vec<char>, usize)>,         // the line before each change, for u in vi
+    inserting: Option<(Vec<char>, usize)>, // the line when the insert mode was entered
+    highlight: Option<(usize, usize)>,     // the text found by an incremental search
+    prefix: Option<usize>,                 // the length searched for by up or down pressed last
+}
+
+// Keeps the terminal in raw mode until dropped.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(0, &mut original) != 0 {
+                return None;
+            }
+            let mut raw = original;
+            raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(0, libc::TCSADRAIN, &raw) != 0 {
+                return None;
+            }
+            Some(RawMode(original))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(0, libc::TCSADRAIN, &self.0);
+        }
+    }
+}
+
+fn write(text: &str) {
+    let mut out = stdout();
+    if let Err(error) = out.write_all(text.as_bytes()).and_then(|_| out.flush()) {
+        eprintln!("{}", error);
+    }
+}
+
+fn columns() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(0, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
+
+// Reads a byte of input, waiting at most this many milliseconds, or for ever
+// when negative. Nothing is returned at the end of the input, after the
+// timeout, or when Ctrl-C interrupts the read.
+fn read_byte(timeout: i32) -> Option<u8> {
+    loop {
+        if timeout >= 0 {
+            let mut poll = libc::pollfd {
+                fd: 0,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut poll, 1, timeout) } == 0 {
+                return None;
+            }
+        }
+        let mut byte = 0u8;
+        let n = unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if n == 1 {
+            return Some(byte);
+        }
+        if n < 0
+            && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR)
+            && !INTERRUPTED.load(Ordering::SeqCst)
+        {
+            continue;
+        }
+        return None;
+    }
+}
+
+// Reads the rest of a character encoded in UTF-8.
+fn read_utf8(first: u8) -> char {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    while bytes.len() < length {
+        match read_byte(ESCAPE_TIMEOUT) {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| text.chars().next())
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+// The key of a control sequence, such as \e[A for the up arrow or \e[1;5C for
+// Ctrl-Right.
+fn sequence(parameters: &str, last: char) -> Key {
+    let modified = parameters.contains(';');
+    match (parameters, last) {
+        (_, 'A') => Key::Up,
+        (_, 'B') => Key::Down,
+        (_, 'C') if modified => Key::WordRight,
+        (_, 'D') if modified => Key::WordLeft,
+        (_, 'C') => Key::Right,
+        (_, 'D') => Key::Left,
+        (_, 'H') | ("1" | "7", '~') => Key::Home,
+        (_, 'F') | ("4" | "8", '~') => Key::End,
+        ("3", '~') => Key::Delete,
+        _ => Key::Unknown,
+    }
+}
+
+fn read_escape() -> Key {
+    let byte = match read_byte(ESCAPE_TIMEOUT) {
+        Some(byte) => byte,
+        None => return Key::Escape,
+    };
+    match byte {
+        b'[' | b'O' => {
+            let mut parameters = String::new();
+            loop {
+                match read_byte(ESCAPE_TIMEOUT) {
+                    Some(last @ 0x40..=0x7e) => return sequence(&parameters, last as char),
+                    Some(byte) => parameters.push(byte as char),
+                    None => return Key::Unknown,
+                }
+            }
+        }
+        0x80.. => Key::Meta(read_utf8(byte)),
+        _ => Key::Meta(byte as char),
+    }
+}
+
+fn read_key() -> Option<Key> {
+    let byte = read_byte(-1)?;
+    Some(match byte {
+        0x1b => read_escape(),
+        0x80.. => Key::Char(read_utf8(byte)),
+        _ => Key::Char(byte as char),
+    })
+}
+
+// The number of columns a character takes on the terminal: none for
+// combining marks, two for the wide characters of East Asian scripts and for
+// emoji.
+fn width(c: char) -> usize {
+    const ZERO: [(u32, u32); 12] = [
+        (0x0300, 0x036f),
+        (0x0483, 0x0489),
+        (0x0591, 0x05bd),
+        (0x0610, 0x061a),
+        (0x064b, 0x065f),
+        (0x1ab0, 0x1aff),
+        (0x1dc0, 0x1dff),
+        (0x200b, 0x200f),
+        (0x20d0, 0x20ff),
+        (0xfe00, 0xfe0f),
+        (0xfe20, 0xfe2f),
+        (0xe0100, 0xe01ef),
+    ];
+    const WIDE: [(u32, u32); 16] = [
+        (0x1100, 0x115f),
+        (0x231a, 0x231b),
+        (0x2e80, 0x303e),
+        (0x3041, 0x33ff),
+        (0x3400, 0x4dbf),
+        (0x4e00, 0x9fff),
+        (0xa000, 0xa4cf),
+        (0xac00, 0xd7a3),
+        (0xf900, 0xfaff),
+        (0xfe30, 0xfe4f),
+        (0xff00, 0xff60),
+        (0xffe0, 0xffe6),
+        (0x1f300, 0x1f64f),
+        (0x1f900, 0x1f9ff),
+        (0x20000, 0x2fffd),
+        (0x30000, 0x3fffd),
+    ];
+    let code = c as u32;
+    if ZERO
+        .iter()
+        .any(|&(first, last)| (first..=last).contains(&code))
+    {
+        0
+    } else if WIDE
+        .iter()
+        .any(|&(first, last)| (first..=last).contains(&code))
+    {
+        2
+    } else {
+        1
+    }
+}
+
+// How a character of the input is shown: control characters as ^X, except
+// for the newlines of the commands of several lines.
+fn display(c: char) -> String {
+    match c {
+        '\n' => "\n".to_string(),
+        '\x7f' => "^?".to_string(),
+        c if c < ' ' => format!("^{}", (c as u8 ^ 0x40) as char),
+        c => c.to_string(),
+    }
+}
+
+// Follows the cursor as the text is written, on a terminal this many
+// columns wide. A character that does not fit at the end of a row goes on
+// the next one. The text between \x01 and \x02 does not move the cursor.
+fn advance(mut position: Position, text: &str, columns: usize) -> Position {
+    let mut invisible = false;
+    for c in text.chars() {
+        match c {
+            '\x01' => invisible = true,
+            '\x02' => invisible = false,
+            _ if invisible => {}
+            '\n' => {
+                if !position.wrapped {
+                    position.row += 1;
+                }
+                position.column = 0;
+                position.wrapped = false;
+            }
+            '\r' => {
+                if position.wrapped {
+                    position.row -= 1;
+                }
+                position.column = 0;
+                position.wrapped = false;
+            }
+            c if c < ' ' => {}
+            c => {
+                let width = width(c);
+                if width == 0 {
+                    continue;
+                }
+                if position.column + width > columns {
+                    position.row += 1;
+                    position.column = 0;
+                }
+                position.column += width;
+                position.wrapped = false;
+                if position.column >= columns {
+                    position.row += 1;
+                    position.column = 0;
+                    position.wrapped = true;
+                }
+            }
+        }
+    }
+    position
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+// The kind of a character for the word motions of vi: blanks, then the
+// characters of words, then punctuation. For the big words of W, B and E,
+// all the characters but the blanks are alike.
+fn class(c: char, big: bool) -> u8 {
+    if c.is_whitespace() {
+        0
+    } else if big || c.is_alphanumeric() || c == '_' {
+        1
+    } else {
+        2
+    }
+}
+
+// The start of the next word, for w.
+fn next_word(buffer: &[char], mut i: usize, big: bool) -> usize {
+    if i >= buffer.len() {
+        return buffer.len();
+    }
+    let kind = class(buffer[i], big);
+    while kind != 0 && i < buffer.len() && class(buffer[i], big) == kind {
+        i += 1;
+    }
+    while i < buffer.len() && class(buffer[i], big) == 0 {
+        i += 1;
+    }
+    i
+}
+
+// The start of the word before, for b.
+fn previous_word(buffer: &[char], mut i: usize, big: bool) -> usize {
+    while i > 0 && class(buffer[i - 1], big) == 0 {
+        i -= 1;
+    }
+    if i > 0 {
+        let kind = class(buffer[i - 1], big);
+        while i > 0 && class(buffer[i - 1], big) == kind {
+            i -= 1;
+        }
+    }
+    i
+}
+
+// The last character of the word, or of the next one when already there,
+// for e.
+fn end_of_word(buffer: &[char], i: usize, big: bool) -> usize {
+    let mut i = i + 1;
+    while i < buffer.len() && class(buffer[i], big) == 0 {
+        i += 1;
+    }
+    if i >= buffer.len() {
+        return buffer.len().saturating_sub(1);
+    }
+    let kind = class(buffer[i], big);
+    while i + 1 < buffer.len() && class(buffer[i + 1], big) == kind {
+        i += 1;
+    }
+    i
+}
+
+// Finds the character after the cursor (f, t) or before it (F, T), as many
+// times as counted. t and T stop next to it. Returns where the cursor goes,
+// and whether the character there goes with the text an operator applies to.
+fn find_char(
+    buffer: &[char],
+    cursor: usize,
+    kind: char,
+    c: char,
+    count: usize,
+) -> Option<(usize, bool)> {
+    let mut i = cursor;
+    for _ in 0..count {
+        i = if matches!(kind, 'f' | 't') {
+            i + 1 + buffer.get(i + 1..)?.iter().position(|&x| x == c)?
+        } else {
+            buffer[..i].iter().rposition(|&x| x == c)?
+        };
+    }
+    match kind {
+        'f' => Some((i, true)),
+        't' => Some((i - 1, true)),
+        'F' => Some((i, false)),
+        _ => Some((i + 1, false)),
+    }
+}
+
+// Finds text in the history from a position in one of its entries, going
+// back to the oldest entry or forward to the newest. With `skip`, a match at
+// the position itself is passed over. Returns the entry and the position of
+// the match.
+fn find(
+    history: &[String],
+    text: &str,
+    (mut entry, position): (usize, usize),
+    backward: bool,
+    skip: bool,
+) -> Option<(usize, usize)> {
+    let pattern: Vec<char> = text.chars().collect();
+    let matches_at = |chars: &[char], i: usize| chars[i..].starts_with(&pattern);
+    // The new line is searched from the newest entry.
+    let mut limit = match entry >= history.len() {
+        true if backward => {
+            entry = history.len().checked_sub(1)?;
+            None
+        }
+        true => return None,
+        false => Some(position),
+    };
+    loop {
+        let chars: Vec<char> = history[entry].chars().collect();
+        let found = if backward {
+            let top = limit.map_or(chars.len(), |limit| limit.min(chars.len()));
+            (0..=top)
+                .rev()
+                .filter(|&i| !(skip && limit == Some(i)))
+                .find(|&i| matches_at(&chars, i))
+        } else {
+            let start = limit.map_or(0, |limit| limit + skip as usize);
+            (start..=chars.len()).find(|&i| matches_at(&chars, i))
+        };
+        if let Some(i) = found {
+            return Some((entry, i));
+        }
+        if backward {
+            entry = entry.checked_sub(1)?;
+        } else {
+            entry += 1;
+            if entry >= history.len() {
+                return None;
+            }
+        }
+        limit = None;
+    }
+}
+
+// Lets the line be edited with $VISUAL or $EDITOR, vi by default. The text
+// saved is shown, and returned to be run.
+fn edit_externally(shell: &mut Shell, text: &str) -> String {
+    let mut template = *b"/tmp/minibash-XXXXXX\0";
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if fd == -1 {
+        eprintln!(
+            "minibash: cannot create temp file: {}",
+            std::io::Error::last_os_error()
+        );
+        return "\n".to_string();
+    }
+    let path = String::from_utf8_lossy(&template[..template.len() - 1]).into_owned();
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    if let Err(error) = file.write_all(format!("{}\n", text).as_bytes()) {
+        eprintln!("minibash: {}: {}", path, error);
+    }
+    drop(file);
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| shell.env.get(name).filter(|value| !value.is_empty()))
+        .cloned()
+        .unwrap_or_else(|| "vi".to_string());
+    let status = execute_script(shell, &format!("{} {}", editor, path), "minibash");
+    let edited = std::fs::read_to_string(&path).unwrap_or_default();
+    let _ = std::fs::remove_file(&path);
+    if status != 0 || edited.trim().is_empty() {
+        return "\n".to_string();
+    }
+    let edited = format!("{}\n", edited.trim_end_matches('\n'));
+    write(&edited);
+    edited
+}
+
+impl Line<'_> {
+    fn text(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    // Draws the prompt and the input again, from the first row of the
+    // prompt, and puts the cursor back in its place. Returns where the input
+    // ends.
+    fn refresh(&mut self) -> Position {
+        let columns = columns();
+        let before: String = self.buffer[..self.cursor]
+            .iter()
+            .map(|&c| display(c))
+            .collect();
+        let after: String = self.buffer[self.cursor..]
+            .iter()
+            .map(|&c| display(c))
+            .collect();
+        let start = advance(Position::default(), &self.prompt, columns);
+        let cursor = advance(start, &before, columns);
+        let end = advance(cursor, &after, columns);
+
+        let mut out = String::new();
+        if self.row > 0 {
+            out.push_str(&format!("\x1b[{}A", self.row));
+        }
+        out.push_str("\r\x1b[J");
+        out.push_str(&printable(&self.prompt));
+        for (i, &c) in self.buffer.iter().enumerate() {
+            if self.highlight.is_some_and(|(from, _)| from == i) {
+                out.push_str("\x1b[7m");
+            }
+            out.push_str(&display(c));
+            if self.highlight.is_some_and(|(_, to)| to == i + 1) {
+                out.push_str("\x1b[27m");
+            }
+        }
+        // Bring the cursor down to the row the input ended on.
+        if end.wrapped {
+            out.push_str("\r\n");
+        }
+        if end.row > cursor.row {
+            out.push_str(&format!("\x1b[{}A", end.row - cursor.row));
+        }
+        out.push('\r');
+        if cursor.column > 0 {
+            out.push_str(&format!("\x1b[{}C", cursor.column));
+        }
+        write(&out);
+        self.row = cursor.row;
+        end
+    }
+
+    // Leaves the cursor on a new row after the input.
+    fn finish(&mut self, mark: &str) {
+        self.cursor = self.buffer.len();
+        let end = self.refresh();
+        if end.wrapped && mark.is_empty() {
+            return;
+        }
+        write(&format!("{}\r\n", mark));
+    }
+
+    fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.buffer.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    // Removes the text between two positions and returns it.
+    fn remove(&mut self, from: usize, to: usize) -> String {
+        let (from, to) = (from.min(to), from.max(to));
+        self.cursor = from;
+        self.buffer.drain(from..to).collect()
+    }
+
+    // The start of the word before the cursor, words being made of the
+    // characters that satisfy the predicate.
+    fn word_start(&self, is_word: fn(char) -> bool) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !is_word(self.buffer[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word(self.buffer[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    // The end of the word after the cursor.
+    fn word_end(&self, is_word: fn(char) -> bool) -> usize {
+        let mut i = self.cursor;
+        while i < self.buffer.len() && !is_word(self.buffer[i]) {
+            i += 1;
+        }
+        while i < self.buffer.len() && is_word(self.buffer[i]) {
+            i += 1;
+        }
+        i
+    }
+}
+
+impl Editor {
+    fn kill(&mut self, line: &mut Line, from: usize, to: usize) {
+        let to = to.min(line.buffer.len());
+        if from < to {
+            self.kill = line.remove(from, to);
+        }
+    }
+
+    // The next key to handle: one left to replay, or one typed. The keys of
+    // a vi command are recorded as they come.
+    fn next_key(&mut self) -> Option<Key> {
+        let key = match self.pending.pop_front() {
+            Some(key) => key,
+            None => read_key()?,
+        };
+        if let Some(recording) = &mut self.recording {
+            recording.push(key.clone());
+        }
+        Some(key)
+    }
+
+    // Shows the entry of the history with this index in place of the line,
+    // the new line being past the last entry. The new line is kept meanwhile.
+    fn recall(&mut self, line: &mut Line, index: usize) {
+        if index > line.history.len() || index == line.entry {
+            return;
+        }
+        if line.entry == line.history.len() {
+            line.saved = line.buffer.clone();
+        }
+        line.buffer = match line.history.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => line.saved.clone(),
+        };
+        line.entry = index;
+        line.cursor = line.buffer.len();
+        line.undo.clear();
+    }
+
+    // Handles a key with the Emacs bindings of readline.
+    fn emacs(&mut self, line: &mut Line, key: Key) -> Outcome {
+        let end = line.buffer.len();
+        let prefix = line.prefix.take();
+        match key {
+            Key::Char('\r' | '\n') => return Outcome::Accept,
+            Key::Char('\x03') => return Outcome::Interrupt,
+            Key::Char('\x04') if line.buffer.is_empty() => return Outcome::Eof,
+            Key::Char('\x04') | Key::Delete if line.cursor < end => {
+                line.buffer.remove(line.cursor);
+            }
+            Key::Char('\x08' | '\x7f') if line.cursor > 0 => {
+                line.cursor -= 1;
+                line.buffer.remove(line.cursor);
+            }
+            Key::Char('\x01') | Key::Home => line.cursor = 0,
+            Key::Char('\x05') | Key::End => line.cursor = end,
+            Key::Char('\x02') | Key::Left => line.cursor = line.cursor.saturating_sub(1),
+            Key::Char('\x06') | Key::Right => line.cursor = (line.cursor + 1).min(end),
+            Key::Meta('b') | Key::WordLeft => line.cursor = line.word_start(is_word),
+            Key::Meta('f') | Key::WordRight => line.cursor = line.word_end(is_word),
+            Key::Char('\x10') if line.entry > 0 => self.recall(line, line.entry - 1),
+            Key::Char('\x0e') => self.recall(line, line.entry + 1),
+            Key::Up => self.prefix_search(line, true, prefix),
+            Key::Down => self.prefix_search(line, false, prefix),
+            Key::Char('\x12') => return self.incremental_search(line, true),
+            Key::Char('\x13') => return self.incremental_search(line, false),
+            Key::Char('\x0b') => self.kill(line, line.cursor, end),
+            Key::Char('\x15') => self.kill(line, 0, line.cursor),
+            Key::Char('\x17') => {
+                let start = line.word_start(|c| !c.is_whitespace());
+                self.kill(line, start, line.cursor);
+            }
+            Key::Meta('\x08' | '\x7f') => self.kill(line, line.word_start(is_word), line.cursor),
+            Key::Meta('d') => self.kill(line, line.cursor, line.word_end(is_word)),
+            Key::Char('\x19') => {
+                let text = self.kill.clone();
+                line.insert(&text);
+            }
+            Key::Char('\x14') if line.cursor > 0 && end > 1 => {
+                // Swaps the characters around the cursor, or the last two at
+                // the end of the line.
+                let i = line.cursor.min(end - 1);
+                line.buffer.swap(i - 1, i);
+                line.cursor = i + 1;
+            }
+            Key::Char('\x0c') => {
+                write("\x1b[H\x1b[2J");
+                line.row = 0;
+            }
+            Key::Char('\x16') => {
+                // Inserts the next character as it is typed.
+                if let Some(Key::Char(c)) = self.next_key() {
+                    line.insert(&c.to_string());
+                }
+            }
+            Key::Char(c) if c >= ' ' && c != '\x7f' || c == '\t' => line.insert(&c.to_string()),
+            _ => {}
+        }
+        Outcome::Edit
+    }
+
+    // Handles a key with the vi bindings: typed in insert mode, the keys are
+    // inserted; in normal mode, entered with Escape, they are commands.
+    fn vi(&mut self, line: &mut Line, key: Key) -> Outcome {
+        if !line.insert {
+            return self.normal(line, key);
+        }
+        match key {
+            Key::Escape => self.leave_insert(line),
+            // Escape typed quickly before a command reads as Alt.
+            Key::Meta(c) => {
+                if let Some(recording) = &mut self.recording {
+                    recording.pop();
+                    recording.push(Key::Escape);
+                }
+                self.leave_insert(line);
+                self.pending.push_front(Key::Char(c));
+            }
+            key => return self.emacs(line, key),
+        }
+        Outcome::Edit
+    }
+
+    fn leave_insert(&mut self, line: &mut Line) {
+        line.insert = false;
+        line.cursor = line.cursor.saturating_sub(1);
+        if let Some(before) = line.inserting.take() {
+            if before.0 != line.buffer {
+                line.undo.push(before);
+            }
+        }
+        if let Some(keys) = self.recording.take() {
+            self.last_change = keys;
+        }
+    }
+
+    // Reads the count typed before a command, if any, and the key after it.
+    fn count(&mut self, mut key: Key) -> Option<(usize, Key)> {
+        let mut count = 0;
+        while let Key::Char(digit @ '0'..='9') = key {
+            if digit == '0' && count == 0 {
+                break;
+            }
+            count = count * 10 + (digit as usize - '0' as usize);
+            key = self.next_key()?;
+        }
+        Some((count.max(1), key))
+    }
+
+    // Handles a command of the normal mode of vi. The commands that change
+    // the line can be undone with u, and repeated with `.`.
+    fn normal(&mut self, line: &mut Line, key: Key) -> Outcome {
+        self.recording = Some(vec![key.clone()]);
+        let (count, key) = match self.count(key) {
+            Some(command) => command,
+            None => return Outcome::Edit,
+        };
+        match key {
+            Key::Char('u') => {
+                if let Some((buffer, cursor)) = line.undo.pop() {
+                    line.buffer = buffer;
+                    line.cursor = cursor;
+                }
+            }
+            Key::Char('.') => {
+                for key in self.last_change.iter().rev() {
+                    self.pending.push_front(key.clone());
+                }
+            }
+            Key::Char('k' | '-' | '\x10') | Key::Up => {
+                self.recall(line, line.entry.saturating_sub(count));
+                line.cursor = 0;
+            }
+            Key::Char('j' | '+' | '\x0e') | Key::Down => {
+                self.recall(line, line.entry + count);
+                line.cursor = 0;
+            }
+            Key::Char(c @ ('/' | '?')) => {
+                if let Some(pattern) = self.read_pattern(line, c) {
+                    if !pattern.is_empty() {
+                        self.search = Some((pattern, c == '/'));
+                    }
+                    self.search_history(line, false);
+                }
+            }
+            Key::Char(c @ ('n' | 'N')) => self.search_history(line, c == 'N'),
+            Key::Char('v') => {
+                self.recording = None;
+                return Outcome::Visual;
+            }
+            key => {
+                let before = (line.buffer.clone(), line.cursor);
+                let outcome = self.command(line, count, key);
+                if line.insert {
+                    line.inserting = Some(before);
+                    return outcome;
+                }
+                if line.buffer != before.0 {
+                    line.undo.push(before);
+                    if let Some(keys) = self.recording.take() {
+                        self.last_change = keys;
+                    }
+                }
+                self.recording = None;
+                line.cursor = line.cursor.min(line.buffer.len().saturating_sub(1));
+                return outcome;
+            }
+        }
+        self.recording = None;
+        line.cursor = line.cursor.min(line.buffer.len().saturating_sub(1));
+        Outcome::Edit
+    }
+
+    // The commands of the normal mode that edit the line or move the cursor.
+    fn command(&mut self, line: &mut Line, count: usize, key: Key) -> Outcome {
+        let end = line.buffer.len();
+        let cursor = line.cursor;
+        match key {
+            Key::Char('\r' | '\n') => return Outcome::Accept,
+            Key::Char('\x03') => return Outcome::Interrupt,
+            Key::Char('\x04') if end == 0 => return Outcome::Eof,
+            Key::Char('\x0c') => {
+                write("\x1b[H\x1b[2J");
+                line.row = 0;
+            }
+            Key::Char('i') => line.insert = true,
+            Key::Char('a') => {
+                line.cursor = (cursor + 1).min(end);
+                line.insert = true;
+            }
+            Key::Char('I') => {
+                line.cursor = 0;
+                line.insert = true;
+            }
+            Key::Char('A') => {
+                line.cursor = end;
+                line.insert = true;
+            }
+            Key::Char('x') | Key::Delete => self.kill(line, cursor, cursor + count),
+            Key::Char('X') => self.kill(line, cursor.saturating_sub(count), cursor),
+            Key::Char('s') => {
+                self.kill(line, cursor, cursor + count);
+                line.insert = true;
+            }
+            Key::Char('S') => {
+                self.kill(line, 0, end);
+                line.insert = true;
+            }
+            Key::Char('C') => {
+                self.kill(line, cursor, end);
+                line.insert = true;
+            }
+            Key::Char('D') => self.kill(line, cursor, end),
+            Key::Char('r') => {
+                if let Some(Key::Char(c)) = self.next_key() {
+                    if cursor + count <= end && c >= ' ' {
+                        line.buffer[cursor..cursor + count].fill(c);
+                        line.cursor = cursor + count - 1;
+                    }
+                }
+            }
+            Key::Char('~') => {
+                for _ in 0..count {
+                    if let Some(&c) = line.buffer.get(line.cursor) {
+                        let toggled: Vec<char> = if c.is_uppercase() {
+                            c.to_lowercase().collect()
+                        } else {
+                            c.to_uppercase().collect()
+                        };
+                        if toggled.len() == 1 {
+                            line.buffer[line.cursor] = toggled[0];
+                        }
+                        line.cursor += 1;
+                    }
+                }
+            }
+            Key::Char(c @ ('p' | 'P')) if !self.kill.is_empty() => {
+                if c == 'p' && end > 0 {
+                    line.cursor += 1;
+                }
+                let text = self.kill.repeat(count);
+                line.insert(&text);
+                line.cursor -= 1;
+            }
+            Key::Char(operator @ ('d' | 'c' | 'y')) => self.operator(line, operator, count),
+            key => {
+                if let Some((target, _)) = self.motion(line, &key, count) {
+                    line.cursor = target;
+                }
+            }
+        }
+        Outcome::Edit
+    }
+
+    // Applies d (delete), c (change) or y (yank) to the text between the
+    // cursor and where a motion goes. Doubled, as in dd, they apply to the
+    // whole line.
+    fn operator(&mut self, line: &mut Line, operator: char, count: usize) {
+        let (more, key) = match self.next_key().and_then(|key| self.count(key)) {
+            Some(motion) => motion,
+            None => return,
+        };
+        let count = count * more;
+        let on_word = line
+            .buffer
+            .get(line.cursor)
+            .is_some_and(|c| !c.is_whitespace());
+        let (from, to) = if key == Key::Char(operator) {
+            (0, line.buffer.len())
+        } else {
+            // As in vi, cw changes the word but not the blanks after it.
+            let key = match key {
+                Key::Char('w') if operator == 'c' && on_word => Key::Char('e'),
+                Key::Char('W') if operator == 'c' && on_word => Key::Char('E'),
+                key => key,
+            };
+            match self.motion(line, &key, count) {
+                Some((target, inclusive)) if target >= line.cursor => {
+                    (line.cursor, target + inclusive as usize)
+                }
+                Some((target, _)) => (target, line.cursor),
+                None => return,
+            }
+        };
+        let to = to.min(line.buffer.len());
+        match operator {
+            'y' => {
+                self.kill = line.buffer[from..to].iter().collect();
+                line.cursor = from;
+            }
+            _ => {
+                self.kill(line, from, to);
+                line.insert = operator == 'c';
+            }
+        }
+    }
+
+    // Where a motion moves the cursor, and whether the character it lands on
+    // goes with the text an operator applies to.
+    fn motion(&mut self, line: &Line, key: &Key, count: usize) -> Option<(usize, bool)> {
+        let buffer = &line.buffer;
+        let end = buffer.len();
+        let cursor = line.cursor;
+        let repeat = |step: &dyn Fn(usize) -> usize| (0..count).fold(cursor, |i, _| step(i));
+        Some(match key {
+            Key::Char('h' | '\x08' | '\x7f') | Key::Left => (cursor.saturating_sub(count), false),
+            Key::Char('l' | ' ') | Key::Right => ((cursor + count).min(end), false),
+            Key::Char('0') | Key::Home => (0, false),
+            Key::Char('^') => (
+                buffer
+                    .iter()
+                    .position(|c| !c.is_whitespace())
+                    .unwrap_or(end),
+                false,
+            ),
+            Key::Char('$') | Key::End => (end, false),
+            Key::Char(c @ ('w' | 'W')) => (repeat(&|i| next_word(buffer, i, *c == 'W')), false),
+            Key::Char(c @ ('b' | 'B')) => (repeat(&|i| previous_word(buffer, i, *c == 'B')), false),
+            Key::Char(c @ ('e' | 'E')) => (repeat(&|i| end_of_word(buffer, i, *c == 'E')), true),
+            Key::Char(kind @ ('f' | 'F' | 't' | 'T')) => {
+                let c = match self.next_key()? {
+                    Key::Char(c) => c,
+                    _ => return None,
+                };
+                self.char_search = Some((*kind, c));
+                find_char(buffer, cursor, *kind, c, count)?
+            }
+            Key::Char(';') => {
+                let (kind, c) = self.char_search?;
+                find_char(buffer, cursor, kind, c, count)?
+            }
+            Key::Char(',') => {
+                let (kind, c) = self.char_search?;
+                let reverse = match kind {
+                    'f' => 'F',
+                    'F' => 'f',
+                    't' => 'T',
+                    _ => 't',
+                };
+                find_char(buffer, cursor, reverse, c, count)?
+            }
+            _ => return None,
+        })
+    }
+
+    // Shows the next entry of the history, back or forward, that starts with
+    // the text before the cursor, which stays where it is. Without such text,
+    // this is the entry before or after, with the cursor at its end. Pressed
+    // again, up or down goes on with the same text.
+    fn prefix_search(&mut self, line: &mut Line, backward: bool, prefix: Option<usize>) {
+        let length = prefix.unwrap_or(line.cursor).min(line.buffer.len());
+        line.prefix = Some(length);
+        let prefix: String = line.buffer[..length].iter().collect();
+        let current = line.text();
+        let matches = |i: &usize| {
+            let entry = &line.history[*i];
+            prefix.is_empty() || entry.starts_with(&prefix) && *entry != current
+        };
+        let found = if backward {
+            (0..line.entry).rev().find(matches)
+        } else {
+            (line.entry + 1..line.history.len()).find(matches)
+        };
+        // Forward from the newest entry comes the new line.
+        let index = match found {
+            Some(index) => index,
+            None if !backward => line.history.len(),
+            None => return,
+        };
+        self.recall(line, index);
+        if length > 0 {
+            line.cursor = length;
+        }
+    }
+
+    // Searches the history as the text to find is typed, back with Ctrl-R and
+    // forward with Ctrl-S, showing the entry found with the text highlighted.
+    // Ctrl-R and Ctrl-S go on to the next match, with the last text searched
+    // for when none was typed, and backspace goes back to the match before.
+    // Enter runs the entry found and Ctrl-G gives the line back as it was;
+    // any other key leaves the entry to be edited, and is then handled.
+    fn incremental_search(&mut self, line: &mut Line, mut backward: bool) -> Outcome {
+        let prompt = std::mem::take(&mut line.prompt);
+        let original = (line.buffer.clone(), line.cursor, line.entry);
+        let mut query = String::new();
+        let mut found = true;
+        let mut position = (line.entry, line.cursor);
+        let mut steps: Vec<((usize, usize), bool)> = Vec::new(); // the state before each character typed
+        let outcome = loop {
+            line.prompt = format!(
+                "({}{}-i-search)`{}': ",
+                if found { "" } else { "failed " },
+                if backward { "reverse" } else { "forward" },
+                query
+            );
+            if position.0 != line.entry {
+                self.recall(line, position.0);
+            }
+            line.cursor = position.1.min(line.buffer.len());
+            line.highlight = (found && !query.is_empty())
+                .then(|| (line.cursor, line.cursor + query.chars().count()));
+            line.refresh();
+
+            let key = match self.next_key() {
+                Some(key) => key,
+                None => break Outcome::Edit,
+            };
+            match key {
+                Key::Char(c @ ('\x12' | '\x13')) => {
+                    backward = c == '\x12';
+                    if query.is_empty() {
+                        query = self.last_query.clone();
+                    }
+                    if query.is_empty() {
+                        continue;
+                    }
+                    match find(line.history, &query, position, backward, true) {
+                        Some(next) => {
+                            position = next;
+                            found = true;
+                        }
+                        None => found = false,
+                    }
+                }
+                Key::Char('\x08' | '\x7f') => {
+                    if let Some((before, ok)) = steps.pop() {
+                        query.pop();
+                        position = before;
+                        found = ok;
+                    }
+                }
+                Key::Char('\x07') => {
+                    (line.buffer, line.cursor, line.entry) = original;
+                    break Outcome::Edit;
+                }
+                Key::Char('\x03') => break Outcome::Interrupt,
+                Key::Char('\r' | '\n') => break Outcome::Accept,
+                Key::Char(c) if c >= ' ' && c != '\x7f' => {
+                    steps.push((position, found));
+                    query.push(c);
+                    if found {
+                        match find(line.history, &query, position, backward, false) {
+                            Some(next) => position = next,
+                            None => found = false,
+                        }
+                    }
+                }
+                key => {
+                    if key != Key::Escape {
+                        self.pending.push_front(key);
+                    }
+                    break Outcome::Edit;
+                }
+            }
+        };
+        if !query.is_empty() {
+            self.last_query = query;
+        }
+        line.prompt = prompt;
+        line.highlight = None;
+        outcome
+    }
+
+    // Reads a pattern to search the history for, typed after / or ? on the
+    // rows of the line being edited.
+    fn read_pattern(&mut self, line: &mut Line, prompt: char) -> Option<String> {
+        let mut pattern = Line {
+            prompt: prompt.to_string(),
+            row: line.row,
+            ..Default::default()
+        };
+        pattern.refresh();
+        let result = loop {
+            match self.next_key() {
+                None | Some(Key::Escape | Key::Char('\x03')) => break None,
+                Some(Key::Char('\r' | '\n')) => break Some(pattern.text()),
+                Some(Key::Char('\x08' | '\x7f')) if pattern.buffer.is_empty() => break None,
+                Some(Key::Up | Key::Down | Key::Char('\x10' | '\x0e')) => {}
+                Some(key) => {
+                    self.emacs(&mut pattern, key);
+                }
+            }
+            pattern.refresh();
+        };
+        line.row = pattern.row;
+        result
+    }
+
+    // Shows the next entry of the history that holds the last pattern, or
+    // starts with it when it begins with ^. / searches back in the history
+    // and ? forward; n goes the same way again, N the other way.
+    fn search_history(&mut self, line: &mut Line, reverse: bool) {
+        let (pattern, backward) = match &self.search {
+            Some((pattern, backward)) => (pattern.clone(), *backward != reverse),
+            None => return,
+        };
+        let matches = |entry: &String| match pattern.strip_prefix('^') {
+            Some(prefix) => entry.starts_with(prefix),
+            None => entry.contains(pattern.as_str()),
+        };
+        let found = if backward {
+            (0..line.entry).rev().find(|&i| matches(&line.history[i]))
+        } else {
+            (line.entry + 1..line.history.len()).find(|&i| matches(&line.history[i]))
+        };
+        if let Some(index) = found {
+            self.recall(line, index);
+            line.cursor = 0;
+        }
+    }
+}
+
+// Reads a line typed at the terminal, after showing the prompt. The line is
+// returned with its newline. Nothing is returned at the end of the input or
+// when Ctrl-C abandons the line, in which case INTERRUPTED is set.
+pub fn read_line(shell: &mut Shell, prompt: &str) -> Option<String> {
+    let raw = match RawMode::enable() {
+        Some(raw) => raw,
+        None => {
+            write(&printable(prompt));
+            return read_line_fd(0, true);
+        }
+    };
+    let mut line = Line {
+        prompt: prompt.to_string(),
+        history: &shell.history.entries,
+        entry: shell.history.entries.len(),
+        insert: true,
+        ..Default::default()
+    };
+    shell.editor.pending.clear();
+    shell.editor.recording = None;
+    line.refresh();
+    loop {
+        // Without a key, the read was interrupted by a signal, whose handler
+        // started a new line, or the terminal is gone.
+        let key = shell.editor.next_key()?;
+        let outcome = if shell.options.vi {
+            shell.editor.vi(&mut line, key)
+        } else {
+            shell.editor.emacs(&mut line, key)
+        };
+        match outcome {
+            Outcome::Edit => {
+                line.refresh();
+            }
+            Outcome::Accept => {
+                line.finish("");
+                return Some(line.text() + "\n");
+            }
+            Outcome::Visual => {
+                line.finish("");
+                drop(raw);
+                return Some(edit_externally(shell, &line.text()));
+            }
+            Outcome::Eof => return None,
+            Outcome::Interrupt => {
+                line.finish("^C");
+                INTERRUPTED.store(true, Ordering::SeqCst);
+                return None;
+            }
+        }
+    }
+}
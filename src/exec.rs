@@ -0,0 +1,975 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{stdout, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::process::exit;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+
+use crate::ast::*;
+use crate::expand::{
+    expand_document, expand_pattern, expand_regex, expand_string, expand_word, expand_words,
+};
+use crate::parser::{is_name, parse, ParseError, Parser};
+use crate::pattern::pattern_match;
+use crate::prompt;
+use crate::regex::Regex;
+use crate::test;
+use crate::{
+    command_matcher, expanded_aliases, is_special_builtin, shell_quote, special_error, Flow, Shell,
+    INTERRUPTED, RUNNING_PROCESS_PID,
+};
+
+// Returns the message of an errno value, without the "(os error N)" suffix
+// that std::io::Error adds.
+pub fn strerror(errno: i32) -> String {
+    unsafe {
+        CStr::from_ptr(libc::strerror(errno))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+pub fn flush_stdout() {
+    if let Err(error) = stdout().flush() {
+        eprintln!("{}", error);
+    }
+}
+
+// Decodes a status returned by waitpid the way $? reports it.
+pub fn wait_status(status: i32) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
+
+pub fn wait_pid(pid: i32) -> i32 {
+    let mut status = 0;
+    unsafe {
+        RUNNING_PROCESS_PID = pid;
+    }
+    let result = loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+            break wait_status(status);
+        }
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            break 127;
+        }
+    };
+    unsafe {
+        RUNNING_PROCESS_PID = 0;
+    }
+    result
+}
+
+// Whether the command running should stop, because of Ctrl-C or because
+// break or continue is unwinding the loops.
+fn interrupted(shell: &Shell) -> bool {
+    shell.flow.is_some() || INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Forks the shell. The child gets the default signal dispositions back, or
+// ignores interrupts when it runs in the background.
+pub fn fork_shell(shell: &mut Shell, background: bool) -> i32 {
+    flush_stdout();
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        shell.subshell = true;
+        let handler = if background {
+            libc::SIG_IGN
+        } else {
+            libc::SIG_DFL
+        };
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGQUIT, handler);
+        }
+    } else if pid == -1 {
+        eprintln!("minibash: fork: {}", strerror(libc::EAGAIN));
+    }
+    pid
+}
+
+pub fn exit_child(status: i32) -> ! {
+    flush_stdout();
+    exit(status);
+}
+
+// Reaps the background processes that have finished.
+pub fn reap_jobs(shell: &mut Shell) {
+    shell.jobs.retain(|&pid| {
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) == 0 }
+    });
+}
+
+pub fn execute_list(shell: &mut Shell, list: &List) -> i32 {
+    for item in &list.items {
+        if interrupted(shell) {
+            break;
+        }
+        if item.background {
+            let pid = fork_shell(shell, true);
+            if pid == 0 {
+                let status = execute_and_or(shell, &item.and_or);
+                exit_child(status);
+            } else if pid > 0 {
+                shell.jobs.push(pid);
+                shell.last_background = pid;
+            }
+            shell.status = 0;
+        } else {
+            execute_and_or(shell, &item.and_or);
+        }
+    }
+    shell.status
+}
+
+fn execute_and_or(shell: &mut Shell, and_or: &AndOr) -> i32 {
+    let last = and_or.rest.len();
+    let mut status = execute_pipeline(shell, &and_or.first, last == 0);
+    for (i, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+        if interrupted(shell) {
+            break;
+        }
+        match connector {
+            Connector::And if status != 0 => continue,
+            Connector::Or if status == 0 => continue,
+            _ => {}
+        }
+        status = execute_pipeline(shell, pipeline, i + 1 == last);
+    }
+    status
+}
+
+// Runs a list whose status is tested, such as the condition of an `if`, so
+// that its failures do not make `set -e` exit the shell.
+fn execute_condition(shell: &mut Shell, list: &List) -> i32 {
+    shell.condition_depth += 1;
+    let status = execute_list(shell, list);
+    shell.condition_depth -= 1;
+    status
+}
+
+// Runs a pipeline. With `set -e`, the shell exits when it fails, unless it is
+// negated, followed by `&&` or `||`, or part of a condition.
+fn execute_pipeline(shell: &mut Shell, pipeline: &Pipeline, last: bool) -> i32 {
+    let tested = !last || pipeline.negated;
+    if tested {
+        shell.condition_depth += 1;
+    }
+    let mut status = if pipeline.commands.len() == 1 {
+        run_command(shell, &pipeline.commands[0])
+    } else {
+        run_pipeline(shell, &pipeline.commands)
+    };
+    if tested {
+        shell.condition_depth -= 1;
+    }
+    if pipeline.negated {
+        status = (status == 0) as i32;
+    }
+    shell.status = status;
+    if status != 0
+        && !tested
+        && shell.options.errexit
+        && shell.condition_depth == 0
+        && !interrupted(shell)
+    {
+        exit_child(status);
+    }
+    status
+}
+
+// Runs every command of a pipeline in its own child, connected by pipes, and
+// returns the status of the last one.
+fn run_pipeline(shell: &mut Shell, commands: &[Command]) -> i32 {
+    let mut pids: Vec<i32> = Vec::new();
+    let mut input = -1;
+
+    for (i, command) in commands.iter().enumerate() {
+        let last = i + 1 == commands.len();
+        let mut fds = [-1, -1];
+        if !last && unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            eprintln!("minibash: pipe: {}", std::io::Error::last_os_error());
+            break;
+        }
+        let pid = fork_shell(shell, false);
+        if pid == 0 {
+            unsafe {
+                if input != -1 {
+                    libc::dup2(input, 0);
+                    libc::close(input);
+                }
+                if !last {
+                    libc::close(fds[0]);
+                    libc::dup2(fds[1], 1);
+                    libc::close(fds[1]);
+                }
+            }
+            let status = run_command(shell, command);
+            exit_child(status);
+        }
+        unsafe {
+            if input != -1 {
+                libc::close(input);
+            }
+            if !last {
+                libc::close(fds[1]);
+            }
+        }
+        input = fds[0];
+        if pid > 0 {
+            pids.push(pid);
+        }
+    }
+
+    let mut status = 1;
+    for pid in pids {
+        status = wait_pid(pid);
+    }
+    status
+}
+
+pub fn run_command(shell: &mut Shell, command: &Command) -> i32 {
+    let substitutions = shell.substitutions.len();
+    let status = match command {
+        Command::Simple(simple) => execute_simple(shell, simple),
+        Command::FunctionDef(name, body) => {
+            shell.functions.insert(name.clone(), Rc::clone(body));
+            0
+        }
+        Command::Compound(compound, redirects) => match apply_redirects(shell, redirects) {
+            Some(saved) => {
+                let status = match compound {
+                    CompoundCommand::BraceGroup(list) => execute_list(shell, list),
+                    CompoundCommand::Subshell(list) => execute_subshell(shell, list),
+                    CompoundCommand::If(clause) => execute_if(shell, clause),
+                    CompoundCommand::While(clause) => execute_while(shell, clause),
+                    CompoundCommand::For(clause) => execute_for(shell, clause),
+                    CompoundCommand::Case(clause) => execute_case(shell, clause),
+                    CompoundCommand::Conditional(expression) => {
+                        execute_conditional(shell, expression)
+                    }
+                };
+                restore_redirects(saved);
+                status
+            }
+            None => 1,
+        },
+    };
+    finish_substitutions(shell, substitutions);
+    status
+}
+
+// Operands of the integer comparisons in `[[ ]]` may name variables, which
+// count as 0 when unset, as in arithmetic.
+fn integer_operand(shell: &Shell, operand: &str) -> Result<i64, String> {
+    let mut operand = operand.trim().to_string();
+    for _ in 0..32 {
+        if let Ok(value) = operand.parse::<i64>() {
+            return Ok(value);
+        }
+        if !is_name(&operand) {
+            break;
+        }
+        operand = match shell.env.get(&operand) {
+            Some(value) if !value.trim().is_empty() => value.trim().to_string(),
+            _ => return Ok(0),
+        };
+    }
+    Err(format!(
+        "{}: syntax error in expression (error token is \"{}\")",
+        operand, operand
+    ))
+}
+
+// Evaluates the expression of `[[ ]]`. Words are expanded without field
+// splitting or globbing, and the right side of `==` and `!=` is a pattern.
+fn conditional(shell: &mut Shell, expression: &CondExpr) -> Result<bool, Option<String>> {
+    Ok(match expression {
+        CondExpr::Word(word) => !expand_string(shell, word).is_empty(),
+        CondExpr::Unary(op, operand) => {
+            let operand = expand_string(shell, operand);
+            test::unary(shell, op, &operand)
+        }
+        CondExpr::Binary(left, op, right) => {
+            let left = expand_string(shell, left);
+            match op.as_str() {
+                "=" | "==" | "!=" => {
+                    let pattern = expand_pattern(shell, right);
+                    pattern_match(&pattern, &left) == (op != "!=")
+                }
+                "=~" => {
+                    let regex = Regex::new(&expand_regex(shell, right)).map_err(|_| None)?;
+                    let captures = regex.captures(&left);
+                    let matched = captures.is_some();
+                    shell
+                        .env
+                        .set_array("BASH_REMATCH", captures.unwrap_or_default());
+                    matched
+                }
+                op if test::is_integer_operator(op) => {
+                    let right = expand_string(shell, right);
+                    let left = integer_operand(shell, &left)?;
+                    test::compare_integers(left, op, integer_operand(shell, &right)?)
+                }
+                op => test::binary(&left, op, &expand_string(shell, right))?,
+            }
+        }
+        CondExpr::Not(expression) => !conditional(shell, expression)?,
+        CondExpr::And(left, right) => conditional(shell, left)? && conditional(shell, right)?,
+        CondExpr::Or(left, right) => conditional(shell, left)? || conditional(shell, right)?,
+        CondExpr::Group(expression) => conditional(shell, expression)?,
+    })
+}
+
+// An invalid regular expression makes the status 2 without any message, as
+// in bash.
+fn execute_conditional(shell: &mut Shell, expression: &CondExpr) -> i32 {
+    match conditional(shell, expression) {
+        Ok(result) => !result as i32,
+        Err(message) => {
+            if let Some(message) = message {
+                eprintln!("minibash: {}", message);
+            }
+            2
+        }
+    }
+}
+
+// Runs a list in a child, so that it cannot change the directory, variables
+// or signal handlers of the shell.
+fn execute_subshell(shell: &mut Shell, list: &List) -> i32 {
+    let pid = fork_shell(shell, false);
+    if pid == 0 {
+        let status = execute_list(shell, list);
+        exit_child(status);
+    }
+    if pid > 0 {
+        wait_pid(pid)
+    } else {
+        1
+    }
+}
+
+fn execute_if(shell: &mut Shell, clause: &IfClause) -> i32 {
+    for (condition, body) in &clause.branches {
+        if execute_condition(shell, condition) == 0 {
+            return execute_list(shell, body);
+        }
+    }
+    match &clause.otherwise {
+        Some(otherwise) => execute_list(shell, otherwise),
+        None => 0,
+    }
+}
+
+// Calls a function with its own positional parameters. Loops around the call
+// cannot be broken from inside it, and a return stops only this call.
+pub fn call_function(shell: &mut Shell, body: &Command, args: &[String]) -> i32 {
+    let positional = std::mem::replace(&mut shell.positional, args.to_vec());
+    let loop_depth = std::mem::take(&mut shell.loop_depth);
+    shell.function_depth += 1;
+    shell.env.push_scope();
+    shell.saved_options.push(None);
+
+    let mut status = run_command(shell, body);
+    if let Some(Flow::Return(returned)) = shell.flow {
+        shell.flow = None;
+        status = returned;
+    }
+
+    if let Some(Some(options)) = shell.saved_options.pop() {
+        shell.options = options;
+    }
+    shell.env.pop_scope();
+    shell.function_depth -= 1;
+    shell.loop_depth = loop_depth;
+    shell.positional = positional;
+    status
+}
+
+// Consumes a pending break or continue once the body of a loop has run.
+// Returns whether the loop has to stop.
+fn loop_done(shell: &mut Shell) -> bool {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return true;
+    }
+    match shell.flow {
+        Some(Flow::Return(_)) => true,
+        Some(Flow::Break(n)) => {
+            shell.flow = if n > 1 {
+                Some(Flow::Break(n - 1))
+            } else {
+                None
+            };
+            true
+        }
+        Some(Flow::Continue(n)) if n > 1 => {
+            shell.flow = Some(Flow::Continue(n - 1));
+            true
+        }
+        Some(Flow::Continue(_)) => {
+            shell.flow = None;
+            false
+        }
+        None => false,
+    }
+}
+
+fn execute_while(shell: &mut Shell, clause: &WhileClause) -> i32 {
+    let mut status = 0;
+    shell.loop_depth += 1;
+    loop {
+        let condition = execute_condition(shell, &clause.condition);
+        if shell.flow.is_none() && (condition == 0) == clause.until {
+            break;
+        }
+        if shell.flow.is_none() && !INTERRUPTED.load(Ordering::SeqCst) {
+            status = execute_list(shell, &clause.body);
+        }
+        if loop_done(shell) {
+            break;
+        }
+    }
+    shell.loop_depth -= 1;
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        status = 130;
+    }
+    status
+}
+
+fn execute_for(shell: &mut Shell, clause: &ForClause) -> i32 {
+    let words = match &clause.words {
+        Some(words) => expand_words(shell, words),
+        None => shell.positional.clone(),
+    };
+    let mut status = 0;
+    shell.loop_depth += 1;
+    for word in words {
+        shell.env.insert(clause.name.clone(), word);
+        status = execute_list(shell, &clause.body);
+        if loop_done(shell) {
+            break;
+        }
+    }
+    shell.loop_depth -= 1;
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        status = 130;
+    }
+    status
+}
+
+fn execute_case(shell: &mut Shell, clause: &CaseClause) -> i32 {
+    let word = expand_string(shell, &clause.word);
+    let mut status = 0;
+    let mut fall_through = false;
+
+    for item in &clause.items {
+        let matched = fall_through
+            || item
+                .patterns
+                .iter()
+                .any(|pattern| pattern_match(&expand_pattern(shell, pattern), &word));
+        if !matched {
+            continue;
+        }
+        status = if item.body.items.is_empty() {
+            0
+        } else {
+            execute_list(shell, &item.body)
+        };
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => fall_through = true,
+            CaseTerminator::Continue => fall_through = false,
+        }
+    }
+    status
+}
+
+fn parse_substitution(shell: &mut Shell, text: &str) -> Option<Vec<List>> {
+    match parse(text, expanded_aliases(shell)) {
+        Ok(commands) => Some(commands),
+        Err(error) => {
+            match error {
+                ParseError::Incomplete => {
+                    eprintln!("minibash: syntax error: unexpected end of file")
+                }
+                ParseError::Syntax(message, _) => eprintln!("minibash: {}", message),
+            }
+            shell.status = 2;
+            shell.substitution_status = Some(2);
+            None
+        }
+    }
+}
+
+// Runs the commands of a script one after the other, so that those before a
+// syntax error still run. Errors are reported with the name of the script
+// and the line.
+pub fn execute_script(shell: &mut Shell, text: &str, name: &str) -> i32 {
+    let mut parser = Parser::new(text, expanded_aliases(shell));
+    loop {
+        // The commands run so far may have defined aliases.
+        parser.set_aliases(expanded_aliases(shell));
+        match parser.next_command() {
+            Ok(Some(list)) => {
+                execute_list(shell, &list);
+                if let Some(Flow::Break(_) | Flow::Continue(_)) = shell.flow {
+                    shell.flow = None;
+                }
+                if shell.flow.is_some() || INTERRUPTED.load(Ordering::SeqCst) {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(ParseError::Incomplete) => {
+                // The error is reported on the line after the last one, which
+                // is unfinished when the text does not end with a newline.
+                let line = text.matches('\n').count() + 1 + usize::from(!text.ends_with('\n'));
+                eprintln!(
+                    "{}: line {}: syntax error: unexpected end of file",
+                    name, line
+                );
+                shell.status = 2;
+                break;
+            }
+            Err(ParseError::Syntax(message, line)) => {
+                eprintln!("{}: line {}: {}", name, line, message);
+                if let Some(text) = text.lines().nth(line.saturating_sub(1)) {
+                    eprintln!("{}: line {}: `{}'", name, line, text);
+                }
+                shell.status = 2;
+                break;
+            }
+        }
+    }
+    shell.status
+}
+
+// Runs the commands read from a file in the current shell. BASH_SOURCE
+// names the file while they run, and a `return` ends them.
+pub fn source_text(shell: &mut Shell, text: &str, path: &str) -> i32 {
+    let sources = shell.env.elements("BASH_SOURCE");
+    let mut inner = vec![path.to_string()];
+    inner.extend(sources.iter().cloned());
+    shell.env.set_array("BASH_SOURCE", inner);
+    shell.source_depth += 1;
+
+    let mut status = execute_script(shell, text, path);
+    if let Some(Flow::Return(returned)) = shell.flow {
+        shell.flow = None;
+        status = returned;
+    }
+
+    shell.source_depth -= 1;
+    if sources.is_empty() {
+        shell.env.remove("BASH_SOURCE");
+    } else {
+        shell.env.set_array("BASH_SOURCE", sources);
+    }
+    status
+}
+
+// Runs a file in the current shell, as `source` does. Returns None when the
+// file cannot be read.
+pub fn source_file(shell: &mut Shell, path: &str) -> Option<i32> {
+    let text = std::fs::read(path).ok()?;
+    Some(source_text(shell, &String::from_utf8_lossy(&text), path))
+}
+
+// Runs a command in a child and returns what it wrote on its standard output,
+// without the trailing newlines.
+pub fn command_substitution(shell: &mut Shell, text: &str) -> String {
+    let commands = match parse_substitution(shell, text) {
+        Some(commands) => commands,
+        None => return String::new(),
+    };
+
+    let mut fds = [-1, -1];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        eprintln!("minibash: pipe: {}", std::io::Error::last_os_error());
+        return String::new();
+    }
+    let pid = fork_shell(shell, false);
+    if pid == 0 {
+        unsafe {
+            libc::close(fds[0]);
+            libc::dup2(fds[1], 1);
+            libc::close(fds[1]);
+        }
+        for command in &commands {
+            execute_list(shell, command);
+        }
+        exit_child(shell.status);
+    }
+
+    unsafe {
+        libc::close(fds[1]);
+    }
+    let mut output: Vec<u8> = Vec::new();
+    let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+    if let Err(error) = pipe.read_to_end(&mut output) {
+        eprintln!("minibash: {}", error);
+    }
+    let status = if pid > 0 { wait_pid(pid) } else { 1 };
+    shell.status = status;
+    shell.substitution_status = Some(status);
+
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    while output.ends_with('\n') {
+        output.pop();
+    }
+    output
+}
+
+// The arguments of declaration builtins that look like assignments are
+// expanded as assignments, without field splitting or globbing.
+fn expand_arguments(shell: &mut Shell, words: &[String]) -> Vec<String> {
+    let declaration = words
+        .first()
+        .is_some_and(|word| ["declare", "export", "local"].contains(&word.as_str()));
+    if !declaration {
+        return expand_words(shell, words);
+    }
+    let mut args: Vec<String> = Vec::new();
+    for word in words {
+        match word.split_once('=') {
+            Some((name, value)) if is_name(name) => {
+                args.push(format!("{}={}", name, expand_string(shell, value)))
+            }
+            _ => args.extend(expand_word(shell, word)),
+        }
+    }
+    args
+}
+
+// Starts a command for <(...), or >(...) when it reads instead, connected to a
+// pipe. Returns the path through which the other end of the pipe is opened.
+pub fn process_substitution(shell: &mut Shell, text: &str, output: bool) -> String {
+    let commands = match parse_substitution(shell, text) {
+        Some(commands) => commands,
+        None => return String::new(),
+    };
+    let mut fds = [-1, -1];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        eprintln!("minibash: pipe: {}", std::io::Error::last_os_error());
+        return String::new();
+    }
+    let (parent_end, child_end, child_fd) = if output {
+        (fds[0], fds[1], 1)
+    } else {
+        (fds[1], fds[0], 0)
+    };
+    let pid = fork_shell(shell, false);
+    if pid == 0 {
+        unsafe {
+            libc::close(parent_end);
+            for &(fd, _) in &shell.substitutions {
+                libc::close(fd);
+            }
+            libc::dup2(child_end, child_fd);
+            libc::close(child_end);
+        }
+        shell.substitutions.clear();
+        for command in &commands {
+            execute_list(shell, command);
+        }
+        exit_child(shell.status);
+    }
+    unsafe {
+        libc::close(child_end);
+    }
+    if pid > 0 {
+        shell.substitutions.push((parent_end, pid));
+    }
+    format!("/dev/fd/{}", parent_end)
+}
+
+// Closes the descriptors of the process substitutions started since the
+// given count, then reaps their commands.
+fn finish_substitutions(shell: &mut Shell, from: usize) {
+    if shell.substitutions.len() <= from {
+        return;
+    }
+    let substitutions: Vec<(i32, i32)> = shell.substitutions.drain(from..).collect();
+    for &(fd, _) in &substitutions {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+    for (_, pid) in substitutions {
+        wait_pid(pid);
+    }
+}
+
+// Prints a command about to run for `set -x`, after PS4.
+fn trace(shell: &mut Shell, command: &str) {
+    let prefix = prompt::prompt(shell, "PS4");
+    eprintln!("{}{}", prompt::printable(&prefix), command);
+}
+
+fn trace_assignment(shell: &mut Shell, name: &str, value: &str) {
+    if shell.options.xtrace {
+        trace(shell, &format!("{}={}", name, shell_quote(value)));
+    }
+}
+
+fn execute_simple(shell: &mut Shell, command: &SimpleCommand) -> i32 {
+    shell.substitution_status = None;
+    let mut args = expand_arguments(shell, &command.words);
+
+    // Without a command, the status is the one of the last command
+    // substitution.
+    if args.is_empty() {
+        for assignment in &command.assignments {
+            let value = expand_string(shell, &assignment.value);
+            trace_assignment(shell, &assignment.name, &value);
+            shell.env.insert(assignment.name.clone(), value);
+        }
+        return match apply_redirects(shell, &command.redirects) {
+            Some(saved) => {
+                restore_redirects(saved);
+                shell.substitution_status.take().unwrap_or(0)
+            }
+            None => 1,
+        };
+    }
+
+    // Assignments before a command only last for that command, in whose
+    // environment they are.
+    let temporary = !command.assignments.is_empty();
+    if temporary {
+        shell.env.push_scope();
+    }
+    for assignment in &command.assignments {
+        let value = expand_string(shell, &assignment.value);
+        trace_assignment(shell, &assignment.name, &value);
+        shell.env.local(&assignment.name, Some(value));
+        shell.env.export(&assignment.name, None);
+    }
+    if shell.options.xtrace {
+        let words: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        trace(shell, &words.join(" "));
+    }
+
+    let status = match apply_redirects(shell, &command.redirects) {
+        Some(saved) => {
+            // An unset parameter under `set -u` abandons the command.
+            let status = if INTERRUPTED.load(Ordering::SeqCst) {
+                1
+            } else {
+                command_matcher(shell, &mut args)
+            };
+            restore_redirects(saved);
+            status
+        }
+        None if is_special_builtin(shell, &args[0]) => special_error(shell, 1),
+        None => 1,
+    };
+    if temporary {
+        shell.env.pop_scope();
+    }
+    status
+}
+
+// Returns a descriptor from which the text can be read, backed by an unlinked
+// temporary file so that its size is not limited by the capacity of a pipe.
+fn here_document(text: &str) -> Result<i32, ()> {
+    let mut template = *b"/tmp/minibash-XXXXXX\0";
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if fd == -1 {
+        eprintln!(
+            "minibash: cannot create temp file for here-document: {}",
+            std::io::Error::last_os_error()
+        );
+        return Err(());
+    }
+    unsafe {
+        libc::unlink(template.as_ptr() as *const libc::c_char);
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    if let Err(error) = file
+        .write_all(text.as_bytes())
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+    {
+        eprintln!("minibash: here-document: {}", error);
+        return Err(());
+    }
+    Ok(file.into_raw_fd())
+}
+
+fn open_redirect(shell: &mut Shell, redirect: &Redirect) -> Result<i32, ()> {
+    if redirect.op == RedirectOp::HereString {
+        let text = expand_string(shell, &redirect.target);
+        return here_document(&(text + "\n"));
+    }
+    if let Some(document) = &redirect.document {
+        // A quote in the delimiter keeps the text from being expanded.
+        if redirect.target.contains(['\'', '"', '\\']) {
+            return here_document(document);
+        }
+        let text = expand_document(shell, document);
+        return here_document(&text);
+    }
+    let fields = expand_word(shell, &redirect.target);
+    if fields.len() != 1 {
+        eprintln!("minibash: {}: ambiguous redirect", redirect.target);
+        return Err(());
+    }
+    let target = &fields[0];
+
+    if redirect.op == RedirectOp::DupRead || redirect.op == RedirectOp::DupWrite {
+        if target == "-" {
+            return Ok(-1);
+        }
+        return match target.parse::<i32>() {
+            Ok(fd) if unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1 => Ok(fd),
+            Ok(fd) => {
+                eprintln!("minibash: {}: {}", fd, strerror(libc::EBADF));
+                Err(())
+            }
+            Err(_) => {
+                eprintln!("minibash: {}: ambiguous redirect", target);
+                Err(())
+            }
+        };
+    }
+
+    let mut options = OpenOptions::new();
+    match redirect.op {
+        RedirectOp::Read => options.read(true),
+        RedirectOp::Write | RedirectOp::Clobber => options.write(true).create(true).truncate(true),
+        RedirectOp::Append => options.append(true).create(true),
+        _ => options.read(true).write(true).create(true),
+    };
+    match options.open(target) {
+        Ok(file) => Ok(file.into_raw_fd()),
+        Err(error) => {
+            eprintln!(
+                "minibash: {}: {}",
+                target,
+                strerror(error.raw_os_error().unwrap_or(0))
+            );
+            Err(())
+        }
+    }
+}
+
+// Performs the redirections on the shell's own descriptors. The returned
+// copies of the original descriptors are put back by restore_redirects.
+pub fn apply_redirects(shell: &mut Shell, redirects: &[Redirect]) -> Option<Vec<(i32, i32)>> {
+    let mut saved: Vec<(i32, i32)> = Vec::new();
+    flush_stdout();
+
+    for redirect in redirects {
+        let fd = match open_redirect(shell, redirect) {
+            Ok(fd) => fd,
+            Err(()) => {
+                restore_redirects(saved);
+                return None;
+            }
+        };
+        unsafe {
+            saved.push((
+                redirect.fd,
+                libc::fcntl(redirect.fd, libc::F_DUPFD_CLOEXEC, 10),
+            ));
+            if fd == -1 {
+                libc::close(redirect.fd);
+            } else if fd != redirect.fd {
+                libc::dup2(fd, redirect.fd);
+                if redirect.op != RedirectOp::DupRead && redirect.op != RedirectOp::DupWrite {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+    Some(saved)
+}
+
+pub fn restore_redirects(saved: Vec<(i32, i32)>) {
+    flush_stdout();
+    for (fd, copy) in saved.into_iter().rev() {
+        unsafe {
+            if copy == -1 {
+                libc::close(fd);
+            } else {
+                libc::dup2(copy, fd);
+                libc::close(copy);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The status of `[[ ]]` with this expression.
+    fn conditional_status(shell: &mut Shell, expression: &str) -> i32 {
+        let commands = parse(&format!("[[ {} ]]", expression), None).unwrap();
+        commands.iter().fold(0, |_, list| execute_list(shell, list))
+    }
+
+    #[test]
+    fn words_are_not_split_or_globbed() {
+        let mut shell = Shell::default();
+        shell.env.insert("x".to_string(), "a b".to_string());
+        shell.env.insert("star".to_string(), "*".to_string());
+        assert_eq!(conditional_status(&mut shell, "$x == 'a b'"), 0);
+        assert_eq!(conditional_status(&mut shell, "-n $unset"), 1);
+        assert_eq!(conditional_status(&mut shell, "$star == '*'"), 0);
+        assert_eq!(conditional_status(&mut shell, "$unset"), 1);
+    }
+
+    #[test]
+    fn right_side_of_equality_is_a_pattern() {
+        let mut shell = Shell::default();
+        shell.env.insert("p".to_string(), "a*".to_string());
+        assert_eq!(conditional_status(&mut shell, "abc == a*"), 0);
+        assert_eq!(conditional_status(&mut shell, "abc == $p"), 0);
+        assert_eq!(conditional_status(&mut shell, "abc == \"$p\""), 1);
+        assert_eq!(conditional_status(&mut shell, "abc != 'a*'"), 0);
+        assert_eq!(conditional_status(&mut shell, "abc == a[bc]?"), 0);
+    }
+
+    #[test]
+    fn operators_and_grouping() {
+        let mut shell = Shell::default();
+        shell.env.insert("n".to_string(), "7".to_string());
+        assert_eq!(conditional_status(&mut shell, "a && ! ( '' || '' )"), 0);
+        assert_eq!(conditional_status(&mut shell, "'' || a && ''"), 1);
+        assert_eq!(conditional_status(&mut shell, "n -gt 5 && $n -lt 10"), 0);
+        assert_eq!(conditional_status(&mut shell, "unset -eq 0"), 0);
+        assert_eq!(conditional_status(&mut shell, "a < b"), 0);
+        assert_eq!(
+            conditional_status(&mut shell, "-d / && -e /no/such/file"),
+            1
+        );
+    }
+
+    #[test]
+    fn regular_expressions_fill_bash_rematch() {
+        let mut shell = Shell::default();
+        assert_eq!(
+            conditional_status(&mut shell, "key=value =~ ^([a-z]+)=(.*)$"),
+            0
+        );
+        assert_eq!(
+            shell.env.elements("BASH_REMATCH"),
+            ["key=value", "key", "value"]
+        );
+        // Quoted parts of the pattern match literally.
+        assert_eq!(conditional_status(&mut shell, "a.c =~ 'a.c'"), 0);
+        assert_eq!(conditional_status(&mut shell, "abc =~ 'a.c'"), 1);
+        assert!(shell.env.elements("BASH_REMATCH").is_empty());
+        assert_eq!(conditional_status(&mut shell, "abc =~ a\\.c"), 1);
+        // An invalid expression is status 2.
+        assert_eq!(conditional_status(&mut shell, "a =~ [a"), 2);
+    }
+}
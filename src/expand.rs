@@ -0,0 +1,577 @@
+use std::sync::atomic::Ordering;
+
+use crate::exec::{command_substitution, exit_child, process_substitution};
+use crate::parser::{backquote_end, is_name, substitution_end};
+use crate::pattern::glob;
+use crate::{Shell, INTERRUPTED};
+
+const DEFAULT_IFS: &str = " \t\n";
+
+// A piece of a word after parameter expansion, before field splitting.
+enum Piece {
+    Literal(String),  // unquoted text written by the user
+    Quoted(String),   // text inside quotes or escaped by a backslash
+    Expanded(String), // unquoted expansion result, subject to field splitting
+    Break,            // boundary between two positional parameters of $@
+}
+
+// A field after splitting. The pattern has the quoted characters escaped for
+// pathname expansion.
+struct Field {
+    text: String,
+    pattern: String,
+    glob: bool,
+}
+
+fn is_name_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+fn is_name_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+fn ifs(shell: &Shell) -> String {
+    match shell.env.get("IFS") {
+        Some(ifs) => ifs.clone(),
+        None => DEFAULT_IFS.to_string(),
+    }
+}
+
+// Splits a name such as `array[1]` into the array and the subscript.
+fn subscript(name: &str) -> Option<(&str, &str)> {
+    name.strip_suffix(']')?.split_once('[')
+}
+
+// The values of a parameter that expands to several words: the positional
+// parameters for `@` and `*`, or the elements of `array[@]` and `array[*]`.
+fn list(shell: &Shell, name: &str) -> Option<Vec<String>> {
+    match name {
+        "@" | "*" => Some(shell.positional.clone()),
+        _ => match subscript(name)? {
+            (array, "@" | "*") if is_name(array) => Some(shell.env.elements(array)),
+            _ => None,
+        },
+    }
+}
+
+// Whether a parameter other than `@` and `*` has a value.
+fn is_set(shell: &Shell, name: &str) -> bool {
+    if let Some((array, index)) = subscript(name) {
+        return index
+            .parse::<usize>()
+            .is_ok_and(|index| index < shell.env.elements(array).len());
+    }
+    match name.parse::<usize>() {
+        Ok(n) => n <= shell.positional.len(),
+        Err(_) => (name.len() == 1 && "$?!#-".contains(name)) || shell.env.get(name).is_some(),
+    }
+}
+
+// Reports the expansion of an unset parameter under `set -u`. A script
+// stops there, while an interactive shell abandons the command line as if
+// Ctrl-C had been pressed.
+fn unbound(shell: &Shell, name: &str) {
+    eprintln!("minibash: {}: unbound variable", name);
+    if !shell.interactive || shell.subshell {
+        exit_child(127);
+    }
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn lookup(shell: &Shell, name: &str) -> String {
+    if let Some(name) = name.strip_prefix('#').filter(|name| !name.is_empty()) {
+        return match list(shell, name) {
+            Some(values) => values.len().to_string(),
+            None => lookup(shell, name).chars().count().to_string(),
+        };
+    }
+    if shell.options.nounset && !is_set(shell, name) {
+        unbound(shell, name);
+        return String::new();
+    }
+    if let Some((array, index)) = subscript(name) {
+        return index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| shell.env.elements(array).get(index).cloned())
+            .unwrap_or_default();
+    }
+    match name {
+        "$" => std::process::id().to_string(),
+        "?" => shell.status.to_string(),
+        "!" => shell.last_background.to_string(),
+        "#" => shell.positional.len().to_string(),
+        "-" => {
+            let mut letters = shell.options.letters();
+            if shell.interactive {
+                letters.push('i');
+            }
+            letters
+        }
+        "0" => shell.name.clone(),
+        "*" | "@" => shell.positional.join(" "),
+        _ => match name.parse::<usize>() {
+            Ok(n) => match n.checked_sub(1) {
+                Some(n) => shell.positional.get(n).cloned().unwrap_or_default(),
+                None => shell.name.clone(),
+            },
+            Err(_) => shell.env.get(name).cloned().unwrap_or_default(),
+        },
+    }
+}
+
+// Parses the name of the parameter following the '$' at chars[i]. Returns the
+// name and the index just past it, or None when the '$' is a literal.
+fn parameter(chars: &[char], i: usize) -> Option<(String, usize)> {
+    let mut j = i + 1;
+    match chars.get(j) {
+        Some('{') => {
+            j += 1;
+            let start = j;
+            while j < chars.len() && chars[j] != '}' {
+                j += 1;
+            }
+            if j == chars.len() {
+                return None;
+            }
+            Some((chars[start..j].iter().collect(), j + 1))
+        }
+        Some(&c) if is_name_start(c) => {
+            let start = j;
+            while j < chars.len() && is_name_char(chars[j]) {
+                j += 1;
+            }
+            Some((chars[start..j].iter().collect(), j))
+        }
+        Some(&c) if "?$!#*@-".contains(c) || c.is_ascii_digit() => Some((c.to_string(), j + 1)),
+        _ => None,
+    }
+}
+
+// Runs the command substitution starting at chars[i], either $(...) or a
+// backquoted command. Returns its output and the index just past it.
+fn substitution(shell: &mut Shell, chars: &[char], i: usize) -> Option<(String, usize)> {
+    if chars[i] == '`' {
+        let end = backquote_end(chars, i)?;
+        let mut text = String::new();
+        let mut j = i + 1;
+        while j + 1 < end {
+            if chars[j] == '\\' && matches!(chars[j + 1], '$' | '`' | '\\') {
+                j += 1;
+            }
+            text.push(chars[j]);
+            j += 1;
+        }
+        return Some((command_substitution(shell, &text), end));
+    }
+    if chars.get(i + 1) != Some(&'(') {
+        return None;
+    }
+    let end = substitution_end(chars, i + 1)?;
+    let text: String = chars[i + 2..end - 1].iter().collect();
+    Some((command_substitution(shell, &text), end))
+}
+
+// Expands the parameters and command substitutions of a raw word and records
+// which parts were quoted.
+fn pieces(shell: &mut Shell, word: &str) -> Vec<Piece> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut literal = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if chars.get(i + 1) == Some(&'\n') => i += 1,
+            '\\' => {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                if i + 1 < chars.len() {
+                    pieces.push(Piece::Quoted(chars[i + 1].to_string()));
+                    i += 1;
+                }
+            }
+            '\'' => {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                let mut quoted = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    quoted.push(chars[i]);
+                    i += 1;
+                }
+                pieces.push(Piece::Quoted(quoted));
+            }
+            '"' => {
+                pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                i = double_quotes(shell, &chars, i + 1, &mut pieces, true);
+            }
+            '<' | '>' if chars.get(i + 1) == Some(&'(') => {
+                if let Some(end) = substitution_end(&chars, i + 1) {
+                    let text: String = chars[i + 2..end - 1].iter().collect();
+                    let path = process_substitution(shell, &text, chars[i] == '<');
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    pieces.push(Piece::Quoted(path));
+                    i = end;
+                    continue;
+                }
+                literal.push(chars[i]);
+            }
+            '$' | '`' => {
+                if let Some((value, next)) = substitution(shell, &chars, i) {
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    pieces.push(Piece::Expanded(value));
+                    i = next;
+                    continue;
+                }
+                match parameter(&chars, i).filter(|_| chars[i] == '$') {
+                    Some((name, next)) => {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                        match list(shell, &name) {
+                            Some(values) => {
+                                for (n, value) in values.into_iter().enumerate() {
+                                    if n > 0 {
+                                        pieces.push(Piece::Break);
+                                    }
+                                    pieces.push(Piece::Expanded(value));
+                                }
+                            }
+                            None => pieces.push(Piece::Expanded(lookup(shell, &name))),
+                        }
+                        i = next;
+                        continue;
+                    }
+                    None => literal.push(chars[i]),
+                }
+            }
+            c => literal.push(c),
+        }
+        i += 1;
+    }
+    pieces.push(Piece::Literal(literal));
+    pieces
+}
+
+// Expands the inside of double quotes, from chars[start] to the closing quote,
+// or to the end of the text when it is not quoted. Returns the index of the
+// closing quote.
+fn double_quotes(
+    shell: &mut Shell,
+    chars: &[char],
+    start: usize,
+    pieces: &mut Vec<Piece>,
+    quoted_text: bool,
+) -> usize {
+    let mut quoted = String::new();
+    let mut vanish = false; // "$@" expands to no field at all without parameters
+    let mut i = start;
+
+    while i < chars.len() && (chars[i] != '"' || !quoted_text) {
+        if chars[i] == '\\'
+            && i + 1 < chars.len()
+            && matches!(chars[i + 1], '$' | '`' | '"' | '\\' | '\n')
+        {
+            if chars[i + 1] != '\n' {
+                quoted.push(chars[i + 1]);
+            }
+            i += 2;
+            continue;
+        }
+        if chars[i] == '$' || chars[i] == '`' {
+            if let Some((value, next)) = substitution(shell, chars, i) {
+                quoted.push_str(&value);
+                i = next;
+                continue;
+            }
+            if let Some((name, next)) = parameter(chars, i).filter(|_| chars[i] == '$') {
+                match list(shell, &name) {
+                    Some(values) if name == "@" || name.ends_with("[@]") => {
+                        vanish |= values.is_empty();
+                        for (n, value) in values.iter().enumerate() {
+                            if n > 0 {
+                                pieces.push(Piece::Quoted(std::mem::take(&mut quoted)));
+                                pieces.push(Piece::Break);
+                            }
+                            quoted.push_str(value);
+                        }
+                    }
+                    Some(values) => {
+                        let separator: String = ifs(shell).chars().take(1).collect();
+                        quoted.push_str(&values.join(&separator));
+                    }
+                    None => quoted.push_str(&lookup(shell, &name)),
+                }
+                i = next;
+                continue;
+            }
+        }
+        quoted.push(chars[i]);
+        i += 1;
+    }
+    if !vanish || !quoted.is_empty() {
+        pieces.push(Piece::Quoted(quoted));
+    }
+    i
+}
+
+// Only space, tab and newline count as IFS whitespace, and only when they are
+// in IFS.
+pub fn is_ifs_whitespace(c: char, ifs: &str) -> bool {
+    matches!(c, ' ' | '\t' | '\n') && ifs.contains(c)
+}
+
+fn push_unquoted(field: &mut Field, c: char) {
+    field.text.push(c);
+    if c == '\\' {
+        field.pattern.push('\\');
+    }
+    field.pattern.push(c);
+    field.glob |= c == '*' || c == '?' || c == '[';
+}
+
+// Splits the unquoted expansion results of a word on the characters of IFS,
+// following the POSIX rules: IFS whitespace is trimmed and collapsed, while
+// each other IFS character delimits a field, possibly an empty one.
+fn field_split(pieces: Vec<Piece>, ifs: &str) -> Vec<Field> {
+    let new_field = || Field {
+        text: String::new(),
+        pattern: String::new(),
+        glob: false,
+    };
+    let mut fields: Vec<Field> = Vec::new();
+    let mut field = new_field();
+    let mut started = false;
+    let mut after_whitespace = false;
+
+    for piece in pieces {
+        match piece {
+            Piece::Literal(text) => {
+                for c in text.chars() {
+                    push_unquoted(&mut field, c);
+                    started = true;
+                    after_whitespace = false;
+                }
+            }
+            Piece::Quoted(text) => {
+                for c in text.chars() {
+                    field.text.push(c);
+                    if "*?[\\".contains(c) {
+                        field.pattern.push('\\');
+                    }
+                    field.pattern.push(c);
+                }
+                started = true;
+                after_whitespace = false;
+            }
+            Piece::Expanded(text) => {
+                for c in text.chars() {
+                    if !ifs.contains(c) {
+                        push_unquoted(&mut field, c);
+                        started = true;
+                        after_whitespace = false;
+                    } else if is_ifs_whitespace(c, ifs) {
+                        if started {
+                            fields.push(std::mem::replace(&mut field, new_field()));
+                            started = false;
+                            after_whitespace = true;
+                        }
+                    } else {
+                        if started || !after_whitespace {
+                            fields.push(std::mem::replace(&mut field, new_field()));
+                        }
+                        started = false;
+                        after_whitespace = false;
+                    }
+                }
+            }
+            Piece::Break => {
+                if started {
+                    fields.push(std::mem::replace(&mut field, new_field()));
+                }
+                started = false;
+                after_whitespace = false;
+            }
+        }
+    }
+    if started {
+        fields.push(field);
+    }
+    fields
+}
+
+// Expands a raw word into zero or more fields. The result is never parsed
+// again, so quotes or operators inside a variable are plain text. Fields with
+// an unquoted pattern character are replaced by the paths they match.
+pub fn expand_word(shell: &mut Shell, word: &str) -> Vec<String> {
+    let pieces = pieces(shell, word);
+    let mut fields: Vec<String> = Vec::new();
+    for field in field_split(pieces, &ifs(shell)) {
+        if field.glob {
+            let paths = glob(&field.pattern);
+            if !paths.is_empty() {
+                fields.extend(paths);
+                continue;
+            }
+        }
+        fields.push(field.text);
+    }
+    fields
+}
+
+pub fn expand_words(shell: &mut Shell, words: &[String]) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for word in words {
+        fields.extend(expand_word(shell, word));
+    }
+    fields
+}
+
+// Expands a word into a pattern, in which the quoted characters are escaped so
+// that they match literally.
+pub fn expand_pattern(shell: &mut Shell, word: &str) -> String {
+    let mut pattern = String::new();
+    for piece in pieces(shell, word) {
+        match piece {
+            Piece::Literal(text) | Piece::Expanded(text) => {
+                pattern.push_str(&text.replace('\\', "\\\\"))
+            }
+            Piece::Quoted(text) => {
+                for c in text.chars() {
+                    if "*?[\\".contains(c) {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+            }
+            Piece::Break => pattern.push(' '),
+        }
+    }
+    pattern
+}
+
+// Expands a word into a regular expression, in which the quoted characters
+// are escaped so that they match literally.
+pub fn expand_regex(shell: &mut Shell, word: &str) -> String {
+    let mut regex = String::new();
+    for piece in pieces(shell, word) {
+        match piece {
+            Piece::Literal(text) | Piece::Expanded(text) => regex.push_str(&text),
+            Piece::Quoted(text) => {
+                for c in text.chars() {
+                    if "\\.[]()*+?{}|^$".contains(c) {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+            }
+            Piece::Break => regex.push(' '),
+        }
+    }
+    regex
+}
+
+// Expands a word into a single string without field splitting, as done for
+// the value of an assignment.
+pub fn expand_string(shell: &mut Shell, word: &str) -> String {
+    let mut string = String::new();
+    for piece in pieces(shell, word) {
+        match piece {
+            Piece::Literal(text) | Piece::Quoted(text) | Piece::Expanded(text) => {
+                string.push_str(&text)
+            }
+            Piece::Break => string.push(' '),
+        }
+    }
+    string
+}
+
+// Expands the parameters and command substitutions of a prompt, the way they
+// are expanded inside double quotes. Quotes are left as they are.
+pub fn expand_prompt(shell: &mut Shell, text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pieces: Vec<Piece> = Vec::new();
+    double_quotes(shell, &chars, 0, &mut pieces, false);
+    let mut prompt = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Literal(text) | Piece::Quoted(text) | Piece::Expanded(text) => {
+                prompt.push_str(&text)
+            }
+            Piece::Break => prompt.push(' '),
+        }
+    }
+    prompt
+}
+
+// Expands the text of a here-document whose delimiter is not quoted. It is
+// expanded like a prompt, except that a backslash before a double quote is
+// kept, so it is doubled first.
+pub fn expand_document(shell: &mut Shell, text: &str) -> String {
+    expand_prompt(shell, &text.replace("\\\"", "\\\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(pieces: Vec<Piece>, ifs: &str) -> Vec<String> {
+        field_split(pieces, ifs)
+            .into_iter()
+            .map(|field| field.text)
+            .collect()
+    }
+
+    fn expanded(text: &str) -> Vec<Piece> {
+        vec![Piece::Expanded(text.to_string())]
+    }
+
+    #[test]
+    fn whitespace_is_trimmed_and_collapsed() {
+        assert_eq!(
+            split(expanded("  a \t b\n\nc  "), DEFAULT_IFS),
+            ["a", "b", "c"]
+        );
+        assert!(split(expanded(" \t\n"), DEFAULT_IFS).is_empty());
+    }
+
+    #[test]
+    fn other_characters_delimit_empty_fields() {
+        assert_eq!(split(expanded("a::b:"), ":"), ["a", "", "b"]);
+        assert_eq!(split(expanded(":a"), ":"), ["", "a"]);
+        assert_eq!(split(expanded("a : b"), " :"), ["a", "b"]);
+        assert_eq!(split(expanded("a :: b"), " :"), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn only_space_tab_and_newline_are_whitespace() {
+        assert_eq!(split(expanded("a\r\rb"), "\r"), ["a", "", "b"]);
+        assert_eq!(split(expanded("\x0ba\x0b"), "\x0b"), ["", "a"]);
+        assert_eq!(split(expanded("a\u{a0}\u{a0}b"), "\u{a0}"), ["a", "", "b"]);
+        assert!(!is_ifs_whitespace(' ', ":"));
+    }
+
+    #[test]
+    fn quoted_and_literal_text_is_not_split() {
+        let pieces = vec![
+            Piece::Literal("x".to_string()),
+            Piece::Quoted("a b".to_string()),
+            Piece::Expanded("c d".to_string()),
+        ];
+        assert_eq!(split(pieces, DEFAULT_IFS), ["xa bc", "d"]);
+    }
+
+    #[test]
+    fn special_parameters_are_set() {
+        let shell = Shell::default();
+        for name in ["$", "?", "!", "#", "-", "0"] {
+            assert!(is_set(&shell, name), "{}", name);
+        }
+        for name in ["", "$?", "1", "UNSET_NAME"] {
+            assert!(!is_set(&shell, name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn empty_ifs_does_not_split() {
+        assert_eq!(split(expanded("a b:c"), ""), ["a b:c"]);
+    }
+}
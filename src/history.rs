@@ -0,0 +1,838 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::exec::strerror;
+use crate::pattern::pattern_match;
+use crate::Shell;
+
+// The history of the commands typed at the prompt. An interactive shell reads
+// it from the file named by HISTFILE once its startup files have run, and
+// writes it back when it exits. HISTSIZE limits the commands kept in memory
+// and HISTFILESIZE the lines kept in the file; HISTCONTROL and HISTIGNORE
+// keep some commands out of it. Lines typed can refer to the commands in the
+// history with the ! of csh, which the shell expands as it reads them.
+
+pub struct History {
+    pub entries: Vec<String>,
+    first: usize,                           // the number of the first entry
+    saved: usize,                           // the entries before this one are in the file already
+    substitution: Option<(String, String)>, // the last :s, for :&
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            entries: Vec::new(),
+            first: 1,
+            saved: 0,
+            substitution: None,
+        }
+    }
+}
+
+impl History {
+    // The number the next command will have, as \! shows it in the prompts.
+    pub fn next_number(&self) -> usize {
+        self.first + self.entries.len()
+    }
+
+    // Drops the oldest entries beyond a limit.
+    fn truncate(&mut self, size: usize) {
+        let excess = self.entries.len().saturating_sub(size);
+        self.entries.drain(..excess);
+        self.first += excess;
+        self.saved = self.saved.saturating_sub(excess);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.entries.remove(index);
+        if index < self.saved {
+            self.saved -= 1;
+        }
+    }
+}
+
+const USAGE: &str = "history [-c] [-d offset] [n] or history -awr [filename]";
+
+// A limit set by a variable. There is none when it is unset, negative or not
+// a number.
+fn limit(shell: &Shell, name: &str) -> Option<usize> {
+    shell.env.get(name).and_then(|value| value.parse().ok())
+}
+
+fn control(shell: &Shell, value: &str) -> bool {
+    shell
+        .env
+        .get("HISTCONTROL")
+        .is_some_and(|control| control.split(':').any(|word| word == value))
+}
+
+fn history_file(shell: &Shell) -> Option<String> {
+    shell
+        .env
+        .get("HISTFILE")
+        .filter(|path| !path.is_empty())
+        .cloned()
+}
+
+// Whether a command is kept out of the history: with HISTCONTROL, a command
+// starting with a space (ignorespace) or the same as the one before
+// (ignoredups, both with ignoreboth); with HISTIGNORE, a command matching one
+// of its patterns, & standing for the command before.
+fn ignored(shell: &Shell, entry: &str) -> bool {
+    let last = shell.history.entries.last().map(|last| last.as_str());
+    if entry.starts_with(' ') && (control(shell, "ignorespace") || control(shell, "ignoreboth")) {
+        return true;
+    }
+    if last == Some(entry) && (control(shell, "ignoredups") || control(shell, "ignoreboth")) {
+        return true;
+    }
+    let patterns = shell.env.get("HISTIGNORE").cloned().unwrap_or_default();
+    patterns
+        .split(':')
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| match pattern {
+            "&" => last == Some(entry),
+            pattern => pattern_match(pattern, entry),
+        })
+}
+
+// Adds a command to the history, unless it is blank or ignored. With
+// HISTCONTROL=erasedups, the same command entered before is removed.
+pub fn add(shell: &mut Shell, entry: &str) {
+    if entry.trim().is_empty() || ignored(shell, entry) {
+        return;
+    }
+    if control(shell, "erasedups") {
+        while let Some(index) = shell.history.entries.iter().position(|old| old == entry) {
+            shell.history.remove(index);
+        }
+    }
+    shell.history.entries.push(entry.to_string());
+    if let Some(size) = limit(shell, "HISTSIZE") {
+        shell.history.truncate(size);
+    }
+}
+
+// The history file holds an entry per line, except that an entry of several
+// lines is preceded by a line of # and their number, which is also how an
+// entry of one line looking like this is written.
+fn is_count(line: &str) -> Option<usize> {
+    let count = line.strip_prefix('#')?;
+    if count.is_empty() || !count.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    count.parse().ok()
+}
+
+fn needs_count(entry: &str) -> bool {
+    entry.contains('\n') || is_count(entry).is_some()
+}
+
+// The lines an entry takes in the file.
+fn file_lines(entry: &str) -> usize {
+    entry.split('\n').count() + usize::from(needs_count(entry))
+}
+
+fn write_entry(file: &mut impl Write, entry: &str) -> std::io::Result<()> {
+    if needs_count(entry) {
+        writeln!(file, "#{}", entry.split('\n').count())?;
+    }
+    writeln!(file, "{}", entry)
+}
+
+// Splits the text of a history file into its entries.
+fn file_entries(text: &str) -> Vec<String> {
+    let mut lines = text.lines();
+    let mut entries = Vec::new();
+    while let Some(line) = lines.next() {
+        match is_count(line) {
+            Some(count) => {
+                let entry: Vec<&str> = lines.by_ref().take(count).collect();
+                entries.push(entry.join("\n"));
+            }
+            None => entries.push(line.to_string()),
+        }
+    }
+    entries
+}
+
+// Adds the entries of a file to the history.
+fn read(shell: &mut Shell, path: &str) -> std::io::Result<()> {
+    let text = std::fs::read(path)?;
+    let text = String::from_utf8_lossy(&text);
+    shell.history.entries.extend(file_entries(&text));
+    if let Some(size) = limit(shell, "HISTSIZE") {
+        shell.history.truncate(size);
+    }
+    shell.history.saved = shell.history.entries.len();
+    Ok(())
+}
+
+// Writes the entries from the one with this index on to a file, replacing
+// what it holds or after it, then drops its oldest entries until it has no
+// more than HISTFILESIZE lines. The file can be read by its owner only.
+fn write(shell: &mut Shell, path: &str, from: usize, append: bool) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .mode(0o600)
+        .open(path)?;
+    for entry in &shell.history.entries[from..] {
+        write_entry(&mut file, entry)?;
+    }
+    drop(file);
+    if let Some(size) = limit(shell, "HISTFILESIZE") {
+        let text = std::fs::read(path)?;
+        let entries = file_entries(&String::from_utf8_lossy(&text));
+        let mut lines: usize = entries.iter().map(|entry| file_lines(entry)).sum();
+        if lines > size {
+            // Only whole entries are dropped.
+            let mut kept = Vec::new();
+            for entry in &entries {
+                if lines <= size {
+                    write_entry(&mut kept, entry)?;
+                } else {
+                    lines -= file_lines(entry);
+                }
+            }
+            std::fs::write(path, kept)?;
+        }
+    }
+    shell.history.saved = shell.history.entries.len();
+    Ok(())
+}
+
+// Reads the history file, once an interactive shell has run its startup
+// files.
+pub fn load(shell: &mut Shell) {
+    if let Some(path) = history_file(shell) {
+        let _ = read(shell, &path);
+    }
+}
+
+// Writes the history file, when an interactive shell exits.
+pub fn save(shell: &mut Shell) {
+    if !shell.interactive || shell.subshell {
+        return;
+    }
+    if let Some(path) = history_file(shell) {
+        if let Err(error) = write(shell, &path, 0, false) {
+            let message = strerror(error.raw_os_error().unwrap_or(0));
+            eprintln!("minibash: {}: {}", path, message);
+        }
+    }
+}
+
+// Lists the history, only its last entries when a count is given, or changes
+// it: -c clears it and -d deletes an entry, given by its number or, when
+// negative, counting back from the end. -a appends the entries added since
+// the history file was last read or written to it, -r adds the lines of the
+// file to the history and -w writes the whole history to it. The file is the
+// one named by HISTFILE unless another one is given.
+pub fn history_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    let mut clear = false;
+    let mut delete: Option<String> = None;
+    let mut action: Option<char> = None;
+    let mut i = 0;
+    while let Some(arg) = args
+        .get(i)
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        for letter in arg.chars().skip(1) {
+            match letter {
+                'c' => clear = true,
+                'd' => {
+                    i += 1;
+                    match args.get(i) {
+                        Some(offset) => delete = Some(offset.clone()),
+                        None => {
+                            eprintln!("minibash: history: -d: option requires an argument");
+                            eprintln!("history: usage: {}", USAGE);
+                            return 2;
+                        }
+                    }
+                }
+                'a' | 'r' | 'w' => action = Some(letter),
+                _ => {
+                    eprintln!("minibash: history: -{}: invalid option", letter);
+                    eprintln!("history: usage: {}", USAGE);
+                    return 2;
+                }
+            }
+        }
+        i += 1;
+    }
+    let args = &args[i..];
+
+    if clear {
+        shell.history = History::default();
+        return 0;
+    }
+    if let Some(offset) = delete {
+        let number = offset.parse::<i64>().ok().map(|number| match number {
+            number if number < 0 => shell.history.next_number() as i64 + number,
+            number => number,
+        });
+        let first = shell.history.first as i64;
+        return match number {
+            Some(number) if number >= first && number < shell.history.next_number() as i64 => {
+                shell.history.remove((number - first) as usize);
+                0
+            }
+            _ => {
+                eprintln!(
+                    "minibash: history: {}: history position out of range",
+                    offset
+                );
+                1
+            }
+        };
+    }
+    if let Some(action) = action {
+        let path = match args.first().cloned().or_else(|| history_file(shell)) {
+            Some(path) => path,
+            None => return 1,
+        };
+        let result = match action {
+            'a' => {
+                let from = shell.history.saved.min(shell.history.entries.len());
+                write(shell, &path, from, true)
+            }
+            'r' => read(shell, &path),
+            _ => write(shell, &path, 0, false),
+        };
+        return match result {
+            Ok(()) => 0,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => 1,
+            Err(error) => {
+                let message = strerror(error.raw_os_error().unwrap_or(0));
+                eprintln!("minibash: history: {}: {}", path, message);
+                1
+            }
+        };
+    }
+
+    if args.len() > 1 {
+        eprintln!("minibash: history: too many arguments");
+        return 1;
+    }
+    let count = match args.first() {
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                eprintln!("minibash: history: {}: numeric argument required", count);
+                return 1;
+            }
+        },
+        None => shell.history.entries.len(),
+    };
+    let skip = shell.history.entries.len().saturating_sub(count);
+    for (n, entry) in shell.history.entries.iter().enumerate().skip(skip) {
+        println!("{:>5}  {}", shell.history.first + n, entry);
+    }
+    0
+}
+
+// Splits a command into words the way the shell reads it: quoted text stays
+// in its word, and the operators are words of their own.
+fn words(command: &str) -> Vec<String> {
+    let chars: Vec<char> = command.chars().collect();
+    let is_operator = |c: char| "|&;<>()".contains(c);
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let mut word = String::new();
+        if is_operator(chars[i]) {
+            while i < chars.len() && is_operator(chars[i]) && word.len() < 2 {
+                word.push(chars[i]);
+                i += 1;
+            }
+            words.push(word);
+            continue;
+        }
+        while i < chars.len() && !chars[i].is_whitespace() && !is_operator(chars[i]) {
+            let c = chars[i];
+            word.push(c);
+            i += 1;
+            match c {
+                '\\' if i < chars.len() => {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                '\'' | '"' => {
+                    while i < chars.len() && chars[i] != c {
+                        if c == '"' && chars[i] == '\\' && i + 1 < chars.len() {
+                            word.push(chars[i]);
+                            i += 1;
+                        }
+                        word.push(chars[i]);
+                        i += 1;
+                    }
+                    if i < chars.len() {
+                        word.push(c);
+                        i += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+fn number(chars: &[char], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    while *i < chars.len() && chars[*i].is_ascii_digit() {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+// Reads a word designator: a word given by its number, ^ for the first
+// argument or $ for the last one; a range of words such as 2-3, 2- (leaving
+// the last one out) or -3 (from the command name); or * for all the arguments
+// and 2* for those from the second. Returns the range of the words taken.
+fn word_range(chars: &[char], i: &mut usize, count: usize) -> Option<(usize, usize)> {
+    let last = count.checked_sub(1)?;
+    let word = |i: &mut usize| match chars.get(*i) {
+        Some('^') => {
+            *i += 1;
+            Some(1)
+        }
+        Some('$') => {
+            *i += 1;
+            Some(last)
+        }
+        _ => number(chars, i),
+    };
+    let (from, to) = match chars.get(*i) {
+        Some('*') => {
+            *i += 1;
+            (1.min(count), count)
+        }
+        Some('-') => {
+            *i += 1;
+            (0, word(i).map_or(last, |to| to + 1))
+        }
+        _ => {
+            let from = word(i)?;
+            match chars.get(*i) {
+                Some('*') => {
+                    *i += 1;
+                    (from, count)
+                }
+                Some('-') => {
+                    *i += 1;
+                    (from, word(i).map_or(last, |to| to + 1))
+                }
+                _ => (from, from + 1),
+            }
+        }
+    };
+    (from <= to && to <= count).then_some((from, to))
+}
+
+// Reads the text of a :s modifier up to the delimiter, which a backslash
+// escapes, or to the end of the line.
+fn delimited(chars: &[char], i: &mut usize, delimiter: char) -> String {
+    let mut text = String::new();
+    while *i < chars.len() && chars[*i] != delimiter && chars[*i] != '\n' {
+        if chars[*i] == '\\' && chars.get(*i + 1) == Some(&delimiter) {
+            *i += 1;
+        }
+        text.push(chars[*i]);
+        *i += 1;
+    }
+    if chars.get(*i) == Some(&delimiter) {
+        *i += 1;
+    }
+    text
+}
+
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// Applies the modifiers after a history reference, from i: h keeps the
+// directory of a path and t its last component, r removes the suffix of a
+// file name and e keeps it; q quotes the text, and x each word of it;
+// s/old/new/ replaces old with new, everywhere with gs, & standing for old
+// in new; & repeats the last replacement; p prints the command without
+// running it.
+fn modify(
+    shell: &mut Shell,
+    chars: &[char],
+    i: &mut usize,
+    mut text: String,
+    print: &mut bool,
+) -> Result<String, String> {
+    while chars.get(*i) == Some(&':') {
+        let start = *i;
+        let mut j = *i + 2;
+        match chars.get(*i + 1) {
+            Some('h') => {
+                if let Some(slash) = text.rfind('/') {
+                    text.truncate(slash);
+                }
+            }
+            Some('t') => {
+                if let Some(slash) = text.rfind('/') {
+                    text = text[slash + 1..].to_string();
+                }
+            }
+            Some(c @ ('r' | 'e')) => {
+                let base = text.rfind('/').map_or(0, |slash| slash + 1);
+                let dot = text[base..].rfind('.').map(|dot| base + dot);
+                text = match (c, dot) {
+                    ('r', Some(dot)) => text[..dot].to_string(),
+                    ('r', None) => text,
+                    (_, Some(dot)) => text[dot..].to_string(),
+                    (_, None) => String::new(),
+                };
+            }
+            Some('p') => *print = true,
+            Some('q') => text = quote(&text),
+            Some('x') => {
+                let quoted: Vec<String> = text.split_whitespace().map(quote).collect();
+                text = quoted.join(" ");
+            }
+            Some('s' | 'g' | '&') => {
+                let global = chars[*i + 1] == 'g';
+                j = *i + 1 + global as usize;
+                let (old, new) = match chars.get(j) {
+                    Some('s') => {
+                        let delimiter = chars.get(j + 1).copied().unwrap_or('/');
+                        j += 2;
+                        let old = delimited(chars, &mut j, delimiter);
+                        let new = delimited(chars, &mut j, delimiter);
+                        match shell.history.substitution.clone() {
+                            Some((last, _)) if old.is_empty() => (last, new),
+                            _ => (old, new),
+                        }
+                    }
+                    Some('&') => {
+                        j += 1;
+                        shell.history.substitution.clone().unwrap_or_default()
+                    }
+                    _ => break,
+                };
+                let spec: String = chars[start..j.min(chars.len())].iter().collect();
+                if old.is_empty() || !text.contains(&old) {
+                    return Err(format!("{}: substitution failed", spec.trim_end()));
+                }
+                let mut replacement = String::new();
+                let mut new_chars = new.chars();
+                while let Some(c) = new_chars.next() {
+                    match c {
+                        '\\' => replacement.extend(new_chars.next()),
+                        '&' => replacement.push_str(&old),
+                        c => replacement.push(c),
+                    }
+                }
+                text = if global {
+                    text.replace(&old, &replacement)
+                } else {
+                    text.replacen(&old, &replacement, 1)
+                };
+                shell.history.substitution = Some((old, new));
+            }
+            _ => break,
+        }
+        *i = j;
+    }
+    Ok(text)
+}
+
+// Expands the history reference at i: an event, which is a command of the
+// history (!! for the last one, !n for the one numbered n, !-n for the n-th
+// from the end, !string for the last one starting with string, !?string? for
+// the last one holding it, !# for the line so far), then the words taken from
+// it and their modifiers. Returns its text and where it ends.
+fn reference(
+    shell: &mut Shell,
+    chars: &[char],
+    start: usize,
+    line: &str,
+    print: &mut bool,
+) -> Result<(String, usize), String> {
+    let history = &shell.history;
+    let spec = |end: usize| {
+        chars[start..end.min(chars.len())]
+            .iter()
+            .take_while(|&&c| c != '\n')
+            .collect::<String>()
+    };
+    let mut i = start + 1;
+    let event = match chars[i] {
+        '!' => {
+            i += 1;
+            history.entries.last().cloned()
+        }
+        '#' => {
+            i += 1;
+            Some(line.to_string())
+        }
+        '^' | '$' | '*' | '%' | ':' => history.entries.last().cloned(),
+        '-' | '0'..='9' => {
+            let back = chars[i] == '-';
+            if back {
+                i += 1;
+            }
+            let index = match number(chars, &mut i) {
+                Some(n) if back => history.entries.len().checked_sub(n),
+                Some(n) => n.checked_sub(history.first),
+                None => None,
+            };
+            index.and_then(|index| history.entries.get(index)).cloned()
+        }
+        '?' => {
+            i += 1;
+            let begin = i;
+            while i < chars.len() && chars[i] != '?' && chars[i] != '\n' {
+                i += 1;
+            }
+            let text: String = chars[begin..i].iter().collect();
+            if chars.get(i) == Some(&'?') {
+                i += 1;
+            }
+            history
+                .entries
+                .iter()
+                .rev()
+                .find(|entry| entry.contains(&text))
+                .cloned()
+        }
+        _ => {
+            let begin = i;
+            while i < chars.len() && !chars[i].is_whitespace() && !":;&|<>()'\"".contains(chars[i])
+            {
+                i += 1;
+            }
+            let text: String = chars[begin..i].iter().collect();
+            history
+                .entries
+                .iter()
+                .rev()
+                .find(|entry| entry.starts_with(&text))
+                .cloned()
+        }
+    };
+    let event = event.ok_or_else(|| format!("{}: event not found", spec(i)))?;
+
+    let designated = match (chars.get(i), chars.get(i + 1)) {
+        (Some(':'), Some(&c)) if c.is_ascii_digit() || "^$*-%".contains(c) => {
+            i += 1;
+            true
+        }
+        (Some(&c), _) => "^$*%".contains(c),
+        _ => false,
+    };
+    let text = if designated {
+        let words = words(&event);
+        match word_range(chars, &mut i, words.len()) {
+            Some((from, to)) => words[from..to].join(" "),
+            None => return Err(format!("{}: bad word specifier", spec(i + 1))),
+        }
+    } else {
+        event
+    };
+    let text = modify(shell, chars, &mut i, text, print)?;
+    Ok((text, i))
+}
+
+// Expands the history references of a line read, outside single quotes and
+// unless escaped by a backslash. A ! followed by a blank, = or ( is left
+// alone, and so are $! and ${!. ^old^new^ at the start of the line stands for
+// !!:s^old^new^. Returns the line expanded, and whether it is to be printed
+// only, or nothing when it has no reference.
+pub fn expand(shell: &mut Shell, line: &str) -> Result<Option<(String, bool)>, String> {
+    let line = match line.strip_prefix('^') {
+        Some(rest) => format!("!!:s^{}", rest),
+        None => line.to_string(),
+    };
+    let chars: Vec<char> = line.chars().collect();
+    let mut expanded = String::new();
+    let (mut single, mut double) = (false, false);
+    let mut found = false;
+    let mut print = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if !single && i + 1 < chars.len() => {
+                expanded.push(c);
+                expanded.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '!' if !single
+                && !expanded.ends_with('$')
+                && !expanded.ends_with("${")
+                && chars.get(i + 1).is_some_and(|&next| {
+                    !next.is_whitespace() && next != '=' && next != '(' && !(double && next == '"')
+                }) =>
+            {
+                let (text, next) = reference(shell, &chars, i, &expanded, &mut print)?;
+                expanded.push_str(&text);
+                found = true;
+                i = next;
+                continue;
+            }
+            _ => {}
+        }
+        expanded.push(c);
+        i += 1;
+    }
+    Ok(found.then_some((expanded, print)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_of_several_lines_read_back_whole() {
+        let entries = [
+            "for i in 1 2",
+            "do echo $i\ndone",
+            "#5",
+            "# 5",
+            "echo \"a\n\nb\"",
+        ];
+        let mut text = Vec::new();
+        for entry in entries {
+            write_entry(&mut text, entry).unwrap();
+        }
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(
+            text,
+            "for i in 1 2\n#2\ndo echo $i\ndone\n#1\n#5\n# 5\n#3\necho \"a\n\nb\"\n"
+        );
+        assert_eq!(file_entries(&text), entries);
+        let lines: usize = entries.iter().map(|entry| file_lines(entry)).sum();
+        assert_eq!(lines, text.lines().count());
+    }
+
+    fn shell_with(entries: &[&str]) -> Shell {
+        let mut shell = Shell::default();
+        shell.history.entries = entries.iter().map(|entry| entry.to_string()).collect();
+        shell
+    }
+
+    fn expanded(shell: &mut Shell, line: &str) -> String {
+        expand(shell, line).unwrap().unwrap().0
+    }
+
+    #[test]
+    fn events() {
+        let mut shell = shell_with(&["echo one", "ls -l /tmp", "echo two"]);
+        assert_eq!(expanded(&mut shell, "!!"), "echo two");
+        assert_eq!(expanded(&mut shell, "!2 x"), "ls -l /tmp x");
+        assert_eq!(expanded(&mut shell, "!-3"), "echo one");
+        assert_eq!(expanded(&mut shell, "!ls"), "ls -l /tmp");
+        assert_eq!(expanded(&mut shell, "!?one?"), "echo one");
+        assert_eq!(expanded(&mut shell, "echo a !#"), "echo a echo a ");
+        assert_eq!(
+            expand(&mut shell, "!nothing").unwrap_err(),
+            "!nothing: event not found"
+        );
+        assert_eq!(expand(&mut shell, "!9").unwrap_err(), "!9: event not found");
+    }
+
+    #[test]
+    fn references_left_alone() {
+        let mut shell = shell_with(&["ls"]);
+        for line in [
+            "echo '!!'",
+            "echo \\!!",
+            "echo ! x",
+            "[ !(a) ]",
+            "echo $!",
+            "echo ${!x}",
+            "echo \"a!\"",
+        ] {
+            assert_eq!(expand(&mut shell, line), Ok(None), "{}", line);
+        }
+    }
+
+    #[test]
+    fn word_designators() {
+        let mut shell = shell_with(&["cp 'a b' c/d.txt e > f"]);
+        assert_eq!(expanded(&mut shell, "!!:0"), "cp");
+        assert_eq!(expanded(&mut shell, "!!:1"), "'a b'");
+        assert_eq!(expanded(&mut shell, "!^"), "'a b'");
+        assert_eq!(expanded(&mut shell, "!$"), "f");
+        assert_eq!(expanded(&mut shell, "!*"), "'a b' c/d.txt e > f");
+        assert_eq!(expanded(&mut shell, "!!:2-3"), "c/d.txt e");
+        assert_eq!(expanded(&mut shell, "!!:2-"), "c/d.txt e >");
+        assert_eq!(expanded(&mut shell, "!!:-2"), "cp 'a b' c/d.txt");
+        assert_eq!(expanded(&mut shell, "!!:3*"), "e > f");
+        assert_eq!(
+            expand(&mut shell, "!!:9").unwrap_err(),
+            "!!:9: bad word specifier"
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        let mut shell = shell_with(&["cat /usr/src/main.tar.gz"]);
+        assert_eq!(expanded(&mut shell, "!$:h"), "/usr/src");
+        assert_eq!(expanded(&mut shell, "!$:t"), "main.tar.gz");
+        assert_eq!(expanded(&mut shell, "!$:r"), "/usr/src/main.tar");
+        assert_eq!(expanded(&mut shell, "!$:e"), ".gz");
+        assert_eq!(expanded(&mut shell, "!$:t:r:r"), "main");
+        assert_eq!(expanded(&mut shell, "!!:q"), "'cat /usr/src/main.tar.gz'");
+        assert_eq!(expanded(&mut shell, "!!:x"), "'cat' '/usr/src/main.tar.gz'");
+        assert_eq!(
+            expand(&mut shell, "!!:p"),
+            Ok(Some(("cat /usr/src/main.tar.gz".to_string(), true)))
+        );
+    }
+
+    #[test]
+    fn substitutions() {
+        let mut shell = shell_with(&["echo aa aa"]);
+        assert_eq!(expanded(&mut shell, "!!:s/aa/b/"), "echo b aa");
+        assert_eq!(expanded(&mut shell, "!!:gs/aa/b/"), "echo b b");
+        assert_eq!(expanded(&mut shell, "!!:s/a/[&]/"), "echo [a]a aa");
+        assert_eq!(expanded(&mut shell, "!!:s|a|\\&|"), "echo &a aa");
+        assert_eq!(expanded(&mut shell, "!!:s/aa/c"), "echo c aa");
+        // & repeats the last one, and an empty old text is the last one.
+        assert_eq!(expanded(&mut shell, "!!:&"), "echo c aa");
+        assert_eq!(expanded(&mut shell, "!!:g&"), "echo c c");
+        assert_eq!(expanded(&mut shell, "!!:s//d/"), "echo d aa");
+        assert_eq!(
+            expand(&mut shell, "!!:s/x/y/").unwrap_err(),
+            ":s/x/y/: substitution failed"
+        );
+    }
+
+    #[test]
+    fn quick_substitution() {
+        let mut shell = shell_with(&["grep fo file"]);
+        assert_eq!(expanded(&mut shell, "^fo^foo^"), "grep foo file");
+        assert_eq!(expanded(&mut shell, "^fo^foo"), "grep foo file");
+        assert_eq!(expanded(&mut shell, "^file^&s^ -n"), "grep fo files -n");
+    }
+
+    #[test]
+    fn files_without_counts_have_an_entry_per_line() {
+        assert_eq!(file_entries("ls\n\ncd /\n"), ["ls", "", "cd /"]);
+        assert_eq!(file_entries("#2\nonly one\n"), ["only one"]);
+    }
+}
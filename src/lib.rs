@@ -1,37 +1,71 @@
 use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::{stdout, Write};
+use std::io::{stdout, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
-use std::process::{exit, Child, Command, Stdio};
+use std::process::{exit, Command};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 
-static mut RUNNING_PROCESS_PID: i32 = 0;
+pub mod ast;
+pub mod editor;
+pub mod exec;
+pub mod expand;
+pub mod history;
+pub mod options;
+pub mod parser;
+pub mod pattern;
+pub mod print;
+pub mod prompt;
+pub mod regex;
+pub mod test;
+pub mod variables;
 
-#[derive(PartialEq, Debug, Clone)]
-pub enum Separator {
-    Ampersand, // &&
-    Pipe,      // |
-    Empty,
-    SemiColon,              // ;
-    WriteRedirection,       // >
-    WriteAppendRedirection, // >>
-}
+use options::Options;
+use variables::{Value, Variables};
+
+pub static mut RUNNING_PROCESS_PID: i32 = 0;
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false); // Ctrl-C was pressed
+pub static EXECUTING: AtomicBool = AtomicBool::new(false); // not waiting at the prompt
+pub static PROMPT: Mutex<String> = Mutex::new(String::new()); // the prompt shown last
 
-pub struct InputOutput {
-    pub file: Option<File>,
-    pub stdin: Stdio,
-    pub stdout: Stdio,
-    pub output: Option<Child>,
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Flow {
+    Break(usize),    // break N
+    Continue(usize), // continue N
+    Return(i32),     // return from the function with this status
 }
 
-#[derive(Clone)]
-pub struct CommandObject {
-    pub text: String,
-    pub separator: Separator,
-    pub status_code: i32,
+#[derive(Default)]
+pub struct Shell {
+    pub name: String, // $0
+    pub interactive: bool,
+    pub login: bool,
+    pub options: Options,
+    pub saved_options: Vec<Option<Options>>, // saved by `local -` in each function called
+    pub condition_depth: usize,              // running commands whose failure `set -e` ignores
+    pub env: Variables,
+    pub status: i32,          // $?
+    pub jobs: Vec<i32>,       // background processes not reaped yet
+    pub last_background: i32, // $!
+    pub flow: Option<Flow>,   // pending break, continue or return
+    pub loop_depth: usize,
+    pub function_depth: usize,
+    pub source_depth: usize,   // files being run by `source`
+    pub command_number: usize, // commands read at the prompt, for \# in PS1
+    pub functions: HashMap<String, Rc<ast::Command>>,
+    pub aliases: Rc<HashMap<String, String>>, // shared with the parsers reading commands
+    pub substitutions: Vec<(i32, i32)>,       // descriptor and pid of each <(...) and >(...)
+    pub subshell: bool,                       // running in a forked child of the shell
+    pub positional: Vec<String>,              // $1, $2...
+    pub substitution_status: Option<i32>,     // status of the last $(...)
+    pub editor: editor::Editor,               // the line editor of the prompt
+    pub history: history::History,            // the commands typed at the prompt
 }
 
-fn cd_update_env(env: &mut HashMap<String, String>) {
+fn cd_update_env(env: &mut Variables) {
     let oldpwd = match env.get("PWD") {
         Some(oldpwd) => oldpwd.to_string(),
         None => return,
@@ -47,7 +81,7 @@ fn cd_update_env(env: &mut HashMap<String, String>) {
     update_env_variable(env, ("PWD".to_string(), pwd));
 }
 
-fn cd_hyphen(env: &mut HashMap<String, String>, arg: &mut String) {
+fn cd_hyphen(env: &mut Variables, arg: &mut String) {
     *arg = match env.get("OLDPWD") {
         Some(arg) => {
             println!("{}", arg);
@@ -60,7 +94,7 @@ fn cd_hyphen(env: &mut HashMap<String, String>, arg: &mut String) {
     }
 }
 
-fn cd_tilde(env: &mut HashMap<String, String>, arg: &mut String) {
+fn cd_tilde(env: &mut Variables, arg: &mut String) {
     let home = match env.get("HOME") {
         Some(home) => home.to_string(),
         None => {
@@ -72,84 +106,90 @@ fn cd_tilde(env: &mut HashMap<String, String>, arg: &mut String) {
     *arg = arg.to_string();
 }
 
-fn cd(env: &mut HashMap<String, String>, arg: &mut String) {
-    match arg.chars().next().unwrap() {
-        '~' => cd_tilde(env, arg),
-        '-' => cd_hyphen(env, arg),
+fn cd(env: &mut Variables, arg: &mut String) -> i32 {
+    match arg.chars().next() {
+        Some('~') => cd_tilde(env, arg),
+        Some('-') => cd_hyphen(env, arg),
         _ => {}
     }
-    if let Err(e) = env::set_current_dir(&Path::new(arg)) {
+    if let Err(e) = env::set_current_dir(Path::new(arg)) {
         eprintln!("{}", e);
+        1
     } else {
         cd_update_env(env);
+        0
     }
 }
 
-fn export_no_args(env: &mut HashMap<String, String>, input_output: InputOutput) {
-    let mut sorted: Vec<_> = env.iter().collect();
-
-    let mut output = match input_output.file {
-        Some(output) => Box::new(output) as Box<dyn Write>,
-        None => Box::new(stdout()) as Box<dyn Write>,
-    };
+fn export_no_args(env: &mut Variables) {
+    let mut output = stdout();
 
-    sorted.sort_by_key(|a| a.0);
-    for (key, value) in sorted {
-        writeln!(output, "declare -x {}=\"{}\"", key, value)
-            .unwrap_or_else(|err| println!("{:?}", err));
+    for (key, variable) in env.visible() {
+        let result = match (&variable.value, variable.exported) {
+            (_, false) => continue,
+            (Some(value), _) => writeln!(output, "declare -x {}={}", key, value.declaration()),
+            (None, _) => writeln!(output, "declare -x {}", key),
+        };
+        result.unwrap_or_else(|err| println!("{:?}", err));
     }
 }
 
-fn update_env_variable(env: &mut HashMap<String, String>, key_value: (String, String)) {
-    *env.get_mut(&key_value.0).unwrap() = key_value.1;
+fn update_env_variable(env: &mut Variables, key_value: (String, String)) {
+    env.insert(key_value.0, key_value.1);
 }
 
-fn export_with_args(env: &mut HashMap<String, String>, args: &mut Vec<String>) {
+fn export_with_args(env: &mut Variables, args: &mut Vec<String>) {
     for arg in args {
-        let mut key_value = arg.split('=');
-
-        let key = key_value.next().unwrap();
-        let value = key_value.next().unwrap();
-
-        env.insert(key.to_string(), value.to_string());
+        match arg.split_once('=') {
+            Some((key, value)) => env.export(key, Some(value.to_string())),
+            None => env.export(arg, None),
+        }
     }
 }
 
-fn unset(env: &mut HashMap<String, String>, args: &mut Vec<String>) {
+fn unset(env: &mut Variables, args: &mut Vec<String>) {
     for arg in args {
         env.remove(arg);
     }
 }
 
-fn print_env(env: &mut HashMap<String, String>, input_output: InputOutput) {
-    let mut output = match input_output.file {
-        Some(output) => Box::new(output) as Box<dyn Write>,
-        None => Box::new(stdout()) as Box<dyn Write>,
-    };
+fn print_env(env: &mut Variables) {
+    let mut output = stdout();
 
-    for (key, value) in env {
-        if key.as_str() != "?" {
-            writeln!(output, "{}={}", key, value).unwrap_or_else(|err| println!("{:?}", err));
-        }
+    for (key, value) in env.exported() {
+        writeln!(output, "{}={}", key, value).unwrap_or_else(|err| println!("{:?}", err));
     }
 }
 
-pub fn save_env() -> HashMap<String, String> {
-    let mut env = HashMap::new();
+pub fn save_env() -> Variables {
+    let mut env = Variables::default();
 
     for (key, value) in env::vars_os() {
         if let (Ok(k), Ok(v)) = (key.into_string(), value.into_string()) {
-            env.insert(k, v);
+            env.export(&k, Some(v));
         }
     }
 
-    return env;
+    env
 }
 
-pub fn exit_handler(args: &mut Vec<String>) {
-    println!("exit");
-    let status_code = if args.len() == 0 {
-        0
+// Runs ~/.minibash_logout when a login shell exits.
+pub fn logout(shell: &mut Shell) {
+    if !shell.login || shell.subshell {
+        return;
+    }
+    shell.login = false; // an `exit` in the file must not run it again
+    if let Some(home) = shell.env.get("HOME").cloned() {
+        exec::source_file(shell, &format!("{}/.minibash_logout", home));
+    }
+}
+
+pub fn exit_handler(shell: &mut Shell, args: &mut [String]) {
+    if shell.interactive && !shell.subshell {
+        eprintln!("exit");
+    }
+    let status_code = if args.is_empty() {
+        shell.status
     } else {
         match args[0].parse() {
             Ok(status_code) => status_code,
@@ -159,60 +199,52 @@ pub fn exit_handler(args: &mut Vec<String>) {
             }
         }
     };
+    logout(shell);
+    history::save(shell);
+    exec::flush_stdout();
     exit(status_code);
 }
 
-fn print_var(env: &mut HashMap<String, String>, variable: &str, input_output: InputOutput) {
-    let mut output = match input_output.file {
-        Some(output) => Box::new(output) as Box<dyn Write>,
-        None => Box::new(stdout()) as Box<dyn Write>,
-    };
+fn print_var(env: &mut Variables, variable: &str) {
+    let mut output = stdout();
 
     match env.get(variable) {
-        Some(var) => {
-            writeln!(output, "{}", var.to_string()).unwrap_or_else(|err| println!("{:?}", err))
-        }
+        Some(var) => writeln!(output, "{}", var).unwrap_or_else(|err| println!("{:?}", err)),
         None => writeln!(output, "${} environment variable not set", variable)
             .unwrap_or_else(|err| println!("{:?}", err)),
     };
 }
 
-pub fn cd_redirector(env: &mut HashMap<String, String>, args: &mut Vec<String>) {
-    if args.len() == 0 {
+pub fn cd_redirector(env: &mut Variables, args: &mut [String]) -> i32 {
+    if args.is_empty() {
         let mut path = match env.get("HOME") {
             Some(path) => path.to_string(),
             None => {
                 eprintln!("$HOME environment variable not set");
-                return;
+                return 1;
             }
         };
-        cd(env, &mut path);
+        cd(env, &mut path)
     } else {
-        cd(env, &mut args[0]);
+        cd(env, &mut args[0])
     }
 }
 
-pub fn unset_redirector(env: &mut HashMap<String, String>, args: &mut Vec<String>) {
-    if args.len() == 0 {
-        return;
-    } else {
+pub fn unset_redirector(env: &mut Variables, args: &mut Vec<String>) {
+    if !args.is_empty() {
         unset(env, args);
     }
 }
 
-pub fn export_redirector(
-    env: &mut HashMap<String, String>,
-    args: &mut Vec<String>,
-    input_output: InputOutput,
-) {
-    if args.len() == 0 {
-        export_no_args(env, input_output);
+pub fn export_redirector(env: &mut Variables, args: &mut Vec<String>) {
+    if args.is_empty() {
+        export_no_args(env);
     } else {
         export_with_args(env, args);
     }
 }
 
-fn echo_option_n(args: &mut Vec<String>, mut output: Box<dyn Write>) {
+fn echo_option_n(args: &mut [String], mut output: impl Write) {
     let mut i = 1;
 
     while i < args.len() {
@@ -224,14 +256,14 @@ fn echo_option_n(args: &mut Vec<String>, mut output: Box<dyn Write>) {
     }
 }
 
-pub fn echo_handler(args: &mut Vec<String>, input_output: InputOutput) {
+pub fn echo_handler(args: &mut [String]) {
     let mut i = 0;
 
-    let mut output = match input_output.file {
-        Some(output) => Box::new(output) as Box<dyn Write>,
-        None => Box::new(stdout()) as Box<dyn Write>,
-    };
+    let mut output = stdout();
 
+    if args.is_empty() {
+        writeln!(output).unwrap_or_else(|err| println!("{:?}", err));
+    }
     while i < args.len() {
         if i == 0 && args[i].as_str() == "-n" {
             echo_option_n(args, output);
@@ -239,7 +271,7 @@ pub fn echo_handler(args: &mut Vec<String>, input_output: InputOutput) {
         }
         write!(output, "{}", args[i]).unwrap_or_else(|err| println!("{:?}", err));
         if i == args.len() - 1 {
-            writeln!(output, "").unwrap_or_else(|err| println!("{:?}", err));
+            writeln!(output).unwrap_or_else(|err| println!("{:?}", err));
         } else {
             write!(output, " ").unwrap_or_else(|err| println!("{:?}", err));
         }
@@ -247,189 +279,713 @@ pub fn echo_handler(args: &mut Vec<String>, input_output: InputOutput) {
     }
 }
 
-fn execute_command(
-    command: &mut CommandObject,
-    executable: String,
-    args: &mut Vec<String>,
-    input_output: InputOutput,
-    env: &mut HashMap<String, String>,
-) -> Option<Child> {
-    let child = Command::new(executable)
-        .args(args)
-        .stdin(input_output.stdin)
-        .stdout(input_output.stdout)
-        .spawn();
+// Quotes a value so that it can be read back by the shell.
+pub fn shell_quote(value: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !value.is_empty() && value.chars().all(safe) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
 
-    match child {
-        Ok(mut child) => {
-            unsafe {
-                RUNNING_PROCESS_PID = child.id() as i32;
+fn set_handler(shell: &mut Shell, args: &mut [String]) -> i32 {
+    if args.is_empty() {
+        for (key, value) in shell.env.iter() {
+            match value {
+                Value::Scalar(value) => println!("{}={}", key, shell_quote(value)),
+                Value::Array(_) => println!("{}={}", key, value.declaration()),
             }
-            match child.wait() {
-                Ok(status) => unsafe {
-                    RUNNING_PROCESS_PID = 0;
-                    match status.code() {
-                        Some(status) => {
-                            command.status_code = status;
-                            env.insert("?".to_string(), status.to_string());
-                        }
-                        None => {
-                            command.status_code = 0;
-                            env.insert("?".to_string(), "0".to_string());
-                        }
-                    }
-                    return Some(child);
-                },
-                Err(e) => eprintln!("{}", e),
-            };
         }
-        Err(e) => eprintln!("{}", e),
+        return 0;
+    }
+    match options::parse_set_options(shell, args) {
+        Ok((start, true)) => {
+            shell.positional = args[start..].to_vec();
+            0
+        }
+        Ok(_) => 0,
+        Err(status) => special_error(shell, status),
     }
-    return None;
 }
 
-pub fn command_matcher(
-    env: &mut HashMap<String, String>,
-    args: &mut Vec<String>,
-    command: &mut CommandObject,
-    input_output: InputOutput,
-) -> Option<Child> {
-    let executable = args.remove(0);
+fn loop_control(shell: &mut Shell, name: &str, args: &[String]) -> i32 {
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            Ok(_) => {
+                eprintln!("minibash: {}: {}: loop count out of range", name, arg);
+                return special_error(shell, 1);
+            }
+            Err(_) => {
+                eprintln!("minibash: {}: {}: numeric argument required", name, arg);
+                return special_error(shell, 1);
+            }
+        },
+    };
+    if shell.loop_depth == 0 {
+        eprintln!(
+            "minibash: {}: only meaningful in a `for', `while', or `until' loop",
+            name
+        );
+        return 0;
+    }
+    let count = count.min(shell.loop_depth);
+    shell.flow = Some(match name {
+        "break" => Flow::Break(count),
+        _ => Flow::Continue(count),
+    });
+    0
+}
 
-    match executable.as_str() {
-        "cd" => cd_redirector(env, args),
-        "clear" => print!("\x1B[2J\x1B[1;1H"),
-        "echo" => echo_handler(args, input_output),
-        "env" => print_env(env, input_output),
-        "exit" => exit_handler(args),
-        "export" => export_redirector(env, args, input_output),
-        "pwd" => print_var(env, "PWD", input_output),
-        "unset" => unset_redirector(env, args),
-        _ => {
-            return execute_command(command, executable, args, input_output, env);
+// Reads a line from the standard input one byte at a time, so that nothing
+// past the newline is consumed. Returns None at the end of the input.
+pub fn read_line_fd(fd: i32, raw: bool) -> Option<String> {
+    let mut line: Vec<u8> = Vec::new();
+    let mut byte = 0u8;
+    loop {
+        let n = unsafe { libc::read(fd, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if n < 0 && std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return None;
+            }
+            continue;
+        }
+        if n <= 0 {
+            if line.is_empty() {
+                return None;
+            }
+            break;
+        }
+        if byte == b'\n' {
+            let backslashes = line.iter().rev().take_while(|&&b| b == b'\\').count();
+            if raw || backslashes % 2 == 0 {
+                line.push(byte);
+                break;
+            }
+            line.pop();
+            continue;
         }
+        line.push(byte);
     }
-    env.insert("?".to_string(), "0".to_string());
-    return None;
+    Some(String::from_utf8_lossy(&line).into_owned())
 }
 
-pub fn splitter(input: &String) -> Vec<String> {
+// Splits a line read by `read` between the variables: each gets one field,
+// the last one gets the rest of the line. Escaped characters never delimit.
+// Fewer fields are returned when the line runs out.
+fn read_split(chars: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let is_space = |&(c, escaped): &(char, bool)| !escaped && expand::is_ifs_whitespace(c, ifs);
+    let is_delimiter = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let mut fields: Vec<String> = Vec::new();
     let mut i = 0;
-    let mut vec: Vec<String> = Vec::new();
-
-    while i < input.len() {
-        let mut arg = String::new();
 
-        if input.chars().nth(i).unwrap() == '"' {
+    while fields.len() + 1 < count {
+        while i < chars.len() && is_space(&chars[i]) {
             i += 1;
-            while input.chars().nth(i).unwrap() != '"' && i + 1 < input.len() {
-                arg.push(input.chars().nth(i).unwrap());
-                i += 1;
-            }
-        } else if input.chars().nth(i).unwrap() == '\'' {
+        }
+        if i == chars.len() {
+            break;
+        }
+        let start = i;
+        while i < chars.len() && !is_delimiter(&chars[i]) {
             i += 1;
-            while input.chars().nth(i).unwrap() != '\'' && i + 1 < input.len() {
-                arg.push(input.chars().nth(i).unwrap());
-                i += 1;
-            }
-        } else {
-            while i < input.len() && input.chars().nth(i).unwrap() != ' ' {
-                if input.chars().nth(i).unwrap() == '"' {
-                    if i + 1 < input.len() {
+        }
+        fields.push(chars[start..i].iter().map(|&(c, _)| c).collect());
+        while i < chars.len() && is_space(&chars[i]) {
+            i += 1;
+        }
+        if i < chars.len() && is_delimiter(&chars[i]) {
+            i += 1;
+        }
+    }
+    while i < chars.len() && is_space(&chars[i]) {
+        i += 1;
+    }
+    let mut end = chars.len();
+    while end > i && is_space(&chars[end - 1]) {
+        end -= 1;
+    }
+    if i < end {
+        fields.push(chars[i..end].iter().map(|&(c, _)| c).collect());
+    }
+    fields
+}
+
+pub fn read_handler(shell: &mut Shell, args: &mut [String]) -> i32 {
+    const USAGE: &str = "read [-r] [-a array] [-p prompt] [name ...]";
+    let mut raw = false;
+    let mut array: Option<String> = None;
+    let mut prompt: Option<String> = None;
+    let mut i = 0;
+
+    while i < args.len() && args[i].starts_with('-') && args[i].len() > 1 {
+        if args[i] == "--" {
+            i += 1;
+            break;
+        }
+        let letters: Vec<char> = args[i].chars().skip(1).collect();
+        for (j, &letter) in letters.iter().enumerate() {
+            match letter {
+                'r' => raw = true,
+                'a' | 'p' => {
+                    // The value is the rest of the argument or the next one.
+                    let value = if j + 1 < letters.len() {
+                        letters[j + 1..].iter().collect()
+                    } else if i + 1 < args.len() {
                         i += 1;
+                        args[i].clone()
                     } else {
-                        break;
+                        eprintln!("minibash: read: -{}: option requires an argument", letter);
+                        eprintln!("read: usage: {}", USAGE);
+                        return 2;
+                    };
+                    match letter {
+                        'a' => array = Some(value),
+                        _ => prompt = Some(value),
                     }
-                    while i < input.len() && input.chars().nth(i).unwrap() != '"' {
-                        arg.push(input.chars().nth(i).unwrap());
-                        i += 1;
-                    }
-                } else {
-                    arg.push(input.chars().nth(i).unwrap());
-                    i += 1;
+                    break;
+                }
+                _ => {
+                    eprintln!("minibash: read: -{}: invalid option", letter);
+                    eprintln!("read: usage: {}", USAGE);
+                    return 2;
                 }
             }
         }
-        if !arg.is_empty() {
-            vec.push(arg);
-        }
         i += 1;
     }
-    return vec;
+    let names = &args[i..];
+    for name in array.iter().chain(names) {
+        if !parser::is_name(name) {
+            eprintln!("minibash: read: `{}': not a valid identifier", name);
+            return 1;
+        }
+    }
+    if let Some(prompt) = prompt {
+        eprint!("{}", prompt);
+    }
+
+    let line = read_line_fd(0, raw);
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        return 130;
+    }
+    let status = match &line {
+        Some(line) if line.ends_with('\n') => 0,
+        _ => 1,
+    };
+    let mut line = line.unwrap_or_default();
+    if line.ends_with('\n') {
+        line.pop();
+    }
+
+    let mut chars: Vec<(char, bool)> = Vec::new();
+    let mut iterator = line.chars();
+    while let Some(c) = iterator.next() {
+        if c == '\\' && !raw {
+            if let Some(next) = iterator.next() {
+                chars.push((next, true));
+            }
+        } else {
+            chars.push((c, false));
+        }
+    }
+
+    let ifs = shell
+        .env
+        .get("IFS")
+        .cloned()
+        .unwrap_or_else(|| " \t\n".to_string());
+    // With -a, every field goes to the array and the names are ignored.
+    if let Some(array) = array {
+        shell
+            .env
+            .set_array(&array, read_split(&chars, &ifs, usize::MAX));
+        return status;
+    }
+    if names.is_empty() {
+        let line: String = chars.iter().map(|&(c, _)| c).collect();
+        shell.env.insert("REPLY".to_string(), line);
+        return status;
+    }
+    let mut fields = read_split(&chars, &ifs, names.len()).into_iter();
+    for name in names {
+        shell
+            .env
+            .insert(name.clone(), fields.next().unwrap_or_default());
+    }
+    status
 }
 
-fn invalid_char_check(c: char) -> bool {
-    return c != ' ' && c != '\'' && c != '"';
+fn return_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    if shell.function_depth == 0 && shell.source_depth == 0 {
+        eprintln!("minibash: return: can only `return' from a function or sourced script");
+        return special_error(shell, 2);
+    }
+    let status = match args.first() {
+        None => shell.status,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(status) => (status & 0xff) as i32,
+            Err(_) => {
+                eprintln!("minibash: return: {}: numeric argument required", arg);
+                special_error(shell, 2)
+            }
+        },
+    };
+    shell.flow = Some(Flow::Return(status));
+    status
 }
 
-pub fn dollar_expander(env: &mut HashMap<String, String>, input: String) -> String {
-    let mut i = 0;
-    let mut between_quotes = false;
-    let mut input = input.clone();
+// Finds the file run by `source`. A name without a slash is looked for in
+// PATH, then in the current directory.
+fn source_path(env: &Variables, name: &str) -> String {
+    if name.contains('/') {
+        return name.to_string();
+    }
+    env.get("PATH")
+        .and_then(|path| {
+            path.split(':')
+                .filter(|directory| !directory.is_empty())
+                .map(|directory| format!("{}/{}", directory.trim_end_matches('/'), name))
+                .find(|path| Path::new(path).is_file())
+        })
+        .unwrap_or_else(|| name.to_string())
+}
 
-    while i < input.len() {
-        if input.chars().nth(i).unwrap() == '\'' && between_quotes == false {
-            i += 1;
-            while input.chars().nth(i).unwrap() != '\'' && i + 1 < input.len() {
-                i += 1;
+// Runs a file in the current shell. The arguments after its name are the
+// positional parameters while it runs.
+fn source_handler(shell: &mut Shell, builtin: &str, args: &[String]) -> i32 {
+    let name = match args.first() {
+        Some(name) => name,
+        None => {
+            eprintln!("minibash: {}: filename argument required", builtin);
+            eprintln!("{}: usage: {} filename [arguments]", builtin, builtin);
+            return special_error(shell, 2);
+        }
+    };
+    let path = source_path(&shell.env, name);
+    if Path::new(&path).is_dir() {
+        eprintln!("minibash: {}: {}: is a directory", builtin, path);
+        return special_error(shell, 1);
+    }
+    let text = match std::fs::read(&path) {
+        Ok(text) => String::from_utf8_lossy(&text).into_owned(),
+        Err(error) => {
+            let errno = error.raw_os_error().unwrap_or(libc::ENOENT);
+            eprintln!("minibash: {}: {}", name, exec::strerror(errno));
+            return special_error(shell, 1);
+        }
+    };
+    let positional =
+        (args.len() > 1).then(|| std::mem::replace(&mut shell.positional, args[1..].to_vec()));
+    let status = exec::source_text(shell, &text, &path);
+    if let Some(positional) = positional {
+        shell.positional = positional;
+    }
+    status
+}
+
+fn is_alias_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| " \t\n|&;()<>$`\\\"'/=".contains(c))
+}
+
+fn print_alias(name: &str, value: &str) {
+    println!("alias {}='{}'", name, value.replace('\'', "'\\''"));
+}
+
+// Defines the aliases given as name=value and prints the others, or all of
+// them without arguments, in a form that can be read back.
+fn alias_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    let args = match args.first().map(|arg| arg.as_str()) {
+        Some("-p" | "--") => &args[1..],
+        Some(option) if option.starts_with('-') && option.len() > 1 => {
+            eprintln!("minibash: alias: {}: invalid option", option);
+            eprintln!("alias: usage: alias [-p] [name[=value] ... ]");
+            return 2;
+        }
+        _ => args,
+    };
+    if args.is_empty() {
+        let mut aliases: Vec<_> = shell.aliases.iter().collect();
+        aliases.sort();
+        for (name, value) in aliases {
+            print_alias(name, value);
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if is_alias_name(name) => {
+                Rc::make_mut(&mut shell.aliases).insert(name.to_string(), value.to_string());
+            }
+            Some((name, _)) => {
+                eprintln!("minibash: alias: `{}': invalid alias name", name);
+                status = 1;
             }
-        } else if input.chars().nth(i).unwrap() == '"' {
-            if between_quotes == true {
-                between_quotes = false;
+            None => match shell.aliases.get(arg) {
+                Some(value) => print_alias(arg, value),
+                None => {
+                    eprintln!("minibash: alias: {}: not found", arg);
+                    status = 1;
+                }
+            },
+        }
+    }
+    status
+}
+
+// Returns the aliases for the parser to expand, none unless the
+// expand_aliases option is set.
+pub fn expanded_aliases(shell: &Shell) -> Option<Rc<HashMap<String, String>>> {
+    shell.options.expand_aliases.then(|| shell.aliases.clone())
+}
+
+fn unalias_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("-a") => {
+            Rc::make_mut(&mut shell.aliases).clear();
+            return 0;
+        }
+        None => {
+            eprintln!("unalias: usage: unalias [-a] name [name ...]");
+            return 2;
+        }
+        _ => {}
+    }
+    let mut status = 0;
+    for name in args {
+        if Rc::make_mut(&mut shell.aliases).remove(name).is_none() {
+            eprintln!("minibash: unalias: {}: not found", name);
+            status = 1;
+        }
+    }
+    status
+}
+
+// The builtins whose errors end a non-interactive shell, as POSIX has it.
+const SPECIAL_BUILTINS: [&str; 9] = [
+    ".", ":", "break", "continue", "exit", "export", "return", "set", "unset",
+];
+
+// Whether a command is a special builtin, which a function of the same name
+// hides.
+pub fn is_special_builtin(shell: &Shell, name: &str) -> bool {
+    SPECIAL_BUILTINS.contains(&name) && !shell.functions.contains_key(name)
+}
+
+// Reports the failure of a special builtin, which ends the shell unless it is
+// interactive. Returns the status otherwise.
+pub fn special_error(shell: &Shell, status: i32) -> i32 {
+    if !shell.interactive {
+        exec::exit_child(status);
+    }
+    status
+}
+
+const BUILTINS: [&str; 27] = [
+    ".", ":", "[", "alias", "break", "cd", "clear", "continue", "declare", "echo", "env", "exit",
+    "export", "false", "history", "local", "pwd", "read", "return", "set", "shopt", "source",
+    "test", "true", "type", "unalias", "unset",
+];
+
+const KEYWORDS: [&str; 19] = [
+    "!", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "then",
+    "until", "while", "{", "}", "[[", "]]",
+];
+
+// Looks a command up in the directories of PATH.
+pub fn find_in_path(env: &Variables, name: &str) -> Option<String> {
+    let executable = |path: &str| {
+        std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if name.contains('/') {
+        return executable(name).then(|| name.to_string());
+    }
+    env.get("PATH")?
+        .split(':')
+        .map(|directory| {
+            let directory = if directory.is_empty() { "." } else { directory };
+            format!("{}/{}", directory.trim_end_matches('/'), name)
+        })
+        .find(|path| executable(path))
+}
+
+fn type_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    let (terse, names) = match args.first().map(|arg| arg.as_str()) {
+        Some("-t") => (true, &args[1..]),
+        _ => (false, args),
+    };
+    let mut status = 0;
+    for name in names {
+        if let Some(value) = shell.aliases.get(name) {
+            if terse {
+                println!("alias");
+            } else {
+                println!("{} is aliased to `{}'", name, value);
+            }
+        } else if let Some(body) = shell.functions.get(name) {
+            if terse {
+                println!("function");
+            } else {
+                println!("{} is a function", name);
+                println!("{}", print::function_definition(name, body));
+            }
+        } else if KEYWORDS.contains(&name.as_str()) {
+            if terse {
+                println!("keyword");
+            } else {
+                println!("{} is a shell keyword", name);
+            }
+        } else if BUILTINS.contains(&name.as_str()) {
+            if terse {
+                println!("builtin");
             } else {
-                between_quotes = true;
+                println!("{} is a shell builtin", name);
             }
-        } else if input.chars().nth(i).unwrap() == '$' {
-            let mut save = i + 1;
-            while invalid_char_check(input.chars().nth(i).unwrap()) && i + 1 < input.len() {
-                i += 1;
+        } else if let Some(path) = find_in_path(&shell.env, name) {
+            if terse {
+                println!("file");
+            } else {
+                println!("{} is {}", name, path);
             }
-            if input.chars().last().unwrap() != '"' && i + 1 == input.len() {
-                i += 1;
+        } else {
+            if !terse {
+                eprintln!("minibash: type: {}: not found", name);
             }
+            status = 1;
+        }
+    }
+    status
+}
 
-            let var = env.get(&input[save..i]);
-            match var {
-                Some(var) => {
-                    save -= 1;
-                    input.replace_range(save..i, var);
-                    if save > var.len() {
-                        i -= save - var.len();
-                    }
+fn print_declarations(variables: Vec<(&String, &variables::Variable)>) {
+    for (name, variable) in variables {
+        let option = match &variable.value {
+            Some(Value::Array(_)) => "-a",
+            _ if variable.exported => "-x",
+            _ => "--",
+        };
+        match &variable.value {
+            Some(value) => println!("declare {} {}={}", option, name, value.declaration()),
+            None => println!("declare {} {}", option, name),
+        }
+    }
+}
+
+// `local` and `declare` create their variables in the scope of the function
+// running, or globally outside of functions and with `declare -g`.
+fn local_handler(shell: &mut Shell, builtin: &str, args: &[String]) -> i32 {
+    if builtin == "local" && shell.function_depth == 0 {
+        eprintln!("minibash: local: can only be used in a function");
+        return 1;
+    }
+    let mut global = shell.function_depth == 0;
+    let mut export = false;
+    let mut print = false;
+    let mut names: Vec<&String> = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-" if builtin == "local" && names.is_empty() => {
+                if let Some(saved @ None) = shell.saved_options.last_mut() {
+                    *saved = Some(shell.options.clone());
                 }
-                None => {}
             }
+            "-g" if builtin == "declare" && names.is_empty() => global = true,
+            "-p" if builtin == "declare" && names.is_empty() => print = true,
+            "-x" if names.is_empty() => export = true,
+            "--" if names.is_empty() => {}
+            option if option.starts_with('-') && names.is_empty() => {
+                eprintln!("minibash: {}: {}: invalid option", builtin, option);
+                return 2;
+            }
+            _ => names.push(arg),
         }
-        i += 1;
     }
-    return input;
+
+    if print && !names.is_empty() {
+        let mut status = 0;
+        for name in names {
+            let visible = shell.env.visible();
+            match visible.into_iter().find(|(visible, _)| *visible == name) {
+                Some(variable) => print_declarations(vec![variable]),
+                None => {
+                    eprintln!("minibash: declare: {}: not found", name);
+                    status = 1;
+                }
+            }
+        }
+        return status;
+    }
+    if args.is_empty() || print {
+        match builtin {
+            "local" => print_declarations(shell.env.locals()),
+            _ => print_declarations(shell.env.visible()),
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if !parser::is_name(name) {
+            eprintln!("minibash: {}: `{}': not a valid identifier", builtin, arg);
+            status = 1;
+            continue;
+        }
+        if global {
+            shell.env.set_global(name, value);
+        } else {
+            shell.env.local(name, value);
+        }
+        if export {
+            shell.env.export(name, None);
+        }
+    }
+    status
 }
 
+fn declare_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    let (option, names) = match args.first().map(|arg| arg.as_str()) {
+        Some(option @ ("-f" | "-F")) => (option, &args[1..]),
+        _ => return local_handler(shell, "declare", args),
+    };
+    let mut sorted: Vec<String> = if names.is_empty() {
+        shell.functions.keys().cloned().collect()
+    } else {
+        names.to_vec()
+    };
+    if names.is_empty() {
+        sorted.sort();
+    }
+    let mut status = 0;
+    for name in sorted {
+        match shell.functions.get(&name) {
+            Some(body) if option == "-f" => {
+                println!("{}", print::function_definition(&name, body))
+            }
+            Some(_) if names.is_empty() => println!("declare -f {}", name),
+            Some(_) => println!("{}", name),
+            None => status = 1,
+        }
+    }
+    status
+}
+
+fn execute_command(shell: &mut Shell, executable: String, args: &mut Vec<String>) -> i32 {
+    let child = Command::new(&executable)
+        .args(args)
+        .env_clear()
+        .envs(shell.env.exported())
+        .spawn();
+
+    match child {
+        Ok(mut child) => {
+            unsafe {
+                RUNNING_PROCESS_PID = child.id() as i32;
+            }
+            let status = child.wait();
+            unsafe {
+                RUNNING_PROCESS_PID = 0;
+            }
+            match status {
+                Ok(status) => match status.code() {
+                    Some(status) => status,
+                    None => 128 + status.signal().unwrap_or(0),
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!("minibash: {}: command not found", executable);
+            127
+        }
+        Err(e) => {
+            eprintln!(
+                "minibash: {}: {}",
+                executable,
+                exec::strerror(e.raw_os_error().unwrap_or(0))
+            );
+            126
+        }
+    }
+}
+
+pub fn command_matcher(shell: &mut Shell, args: &mut Vec<String>) -> i32 {
+    let executable = args.remove(0);
+    if let Some(body) = shell.functions.get(&executable).cloned() {
+        return exec::call_function(shell, &body, args);
+    }
+    let env = &mut shell.env;
+
+    match executable.as_str() {
+        ":" | "true" => {}
+        "[" | "test" => return test::test_handler(shell, &executable, args),
+        "alias" => return alias_handler(shell, args),
+        "break" | "continue" => return loop_control(shell, &executable, args),
+        "declare" => return declare_handler(shell, args),
+        "history" => return history::history_handler(shell, args),
+        "false" => return 1,
+        "local" => return local_handler(shell, "local", args),
+        "read" => return read_handler(shell, args),
+        "return" => return return_handler(shell, args),
+        "." | "source" => return source_handler(shell, &executable, args),
+        "set" => return set_handler(shell, args),
+        "shopt" => return options::shopt_handler(shell, args),
+        "type" => return type_handler(shell, args),
+        "unalias" => return unalias_handler(shell, args),
+        "cd" => return cd_redirector(env, args),
+        "clear" => print!("\x1B[2J\x1B[1;1H"),
+        "echo" => echo_handler(args),
+        "env" => print_env(env),
+        "exit" => exit_handler(shell, args),
+        "export" => export_redirector(env, args),
+        "pwd" => print_var(env, "PWD"),
+        "unset" if args.first().is_some_and(|arg| arg == "-f") => {
+            for name in &args[1..] {
+                shell.functions.remove(name);
+            }
+        }
+        "unset" => unset_redirector(env, args),
+        _ => {
+            return execute_command(shell, executable, args);
+        }
+    }
+    0
+}
+
+/// # Safety
+///
+/// Meant to be installed with `libc::sigaction`, it reads the pid of the
+/// running process from a static.
 #[allow(unused_variables)]
 pub unsafe extern "C" fn handle_sigint(sig: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    // At the prompt, the shell shows a new one once the read is interrupted.
     if RUNNING_PROCESS_PID == 0 {
         println!();
-        print!("minibash-3.2$ ");
-        if let Err(error) = stdout().flush() {
-            eprintln!("{}", error);
-        }
     } else {
         libc::kill(RUNNING_PROCESS_PID, libc::SIGCONT);
         println!();
     }
 }
 
-#[allow(unused_variables)]
-pub unsafe extern "C" fn handle_sigusr1(sig: libc::c_int) {
-    println!("exit");
-    exit(0);
-}
-
+/// # Safety
+///
+/// Meant to be installed with `libc::signal`, it reads the pid of the
+/// running process from a static.
 #[allow(unused_variables)]
 pub unsafe extern "C" fn handle_sigquit(sig: libc::c_int) {
     if RUNNING_PROCESS_PID == 0 {
-        print!("{}", "\r\r");
-        print!("minibash-3.2$ ");
+        print!("\r\r");
+        if let Ok(prompt) = PROMPT.try_lock() {
+            print!("{}", prompt);
+        }
         if let Err(error) = stdout().flush() {
             eprintln!("{}", error);
         }
@@ -439,7 +995,7 @@ pub unsafe extern "C" fn handle_sigquit(sig: libc::c_int) {
     }
 }
 
-pub fn update_shlvl(env: &mut HashMap<String, String>) {
+pub fn update_shlvl(env: &mut Variables) {
     let string_var = env.get("SHLVL");
     match string_var {
         Some(string_var) => {
@@ -452,76 +1008,37 @@ pub fn update_shlvl(env: &mut HashMap<String, String>) {
     }
 }
 
-pub fn arg_split(input: &mut String) -> Vec<CommandObject> {
-    let mut i = 0;
-    let mut j = 0;
-    let mut commands: Vec<CommandObject> = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    while i < input.len() {
-        if input.chars().nth(i).unwrap() == '\'' {
-            i += 1;
-            while i + 1 < input.len() && input.chars().nth(i).unwrap() != '\'' {
-                i += 1;
-            }
-        } else if input.chars().nth(i).unwrap() == '"' {
-            i += 1;
-            while i + 1 < input.len() && input.chars().nth(i).unwrap() != '"' {
-                i += 1;
-            }
-        } else if i + 1 < input.len() && input.chars().nth(i).unwrap() == '&' {
-            commands.push(CommandObject {
-                text: input[j..i].trim().to_string(),
-                separator: Separator::Ampersand,
-                status_code: 0,
-            });
-            i += 1;
-            j = i + 2;
-        } else if i + 1 < input.len()
-            && input.chars().nth(i).unwrap() == '>'
-            && input.chars().nth(i + 1).unwrap() == '>'
-        {
-            commands.push(CommandObject {
-                text: input[j..i].trim().to_string(),
-                separator: Separator::WriteAppendRedirection,
-                status_code: 0,
-            });
-            i += 1;
-            j = i + 2;
-        } else if input.chars().nth(i).unwrap() == '|' {
-            commands.push(CommandObject {
-                text: input[j..i].trim().to_string(),
-                separator: Separator::Pipe,
-                status_code: 0,
-            });
-            i += 1;
-            j = i + 1;
-        } else if input.chars().nth(i).unwrap() == ';' {
-            commands.push(CommandObject {
-                text: input[j..i].trim().to_string(),
-                separator: Separator::SemiColon,
-                status_code: 0,
-            });
-            i += 1;
-            j = i + 1;
-        } else if input.chars().nth(i).unwrap() == '>' {
-            commands.push(CommandObject {
-                text: input[j..i].trim().to_string(),
-                separator: Separator::WriteRedirection,
-                status_code: 0,
-            });
-            i += 1;
-            j = i + 1;
-        }
-        i += 1;
+    fn split(line: &str, ifs: &str, count: usize) -> Vec<String> {
+        let chars: Vec<(char, bool)> = line.chars().map(|c| (c, false)).collect();
+        read_split(&chars, ifs, count)
     }
 
-    if j < input.len() {
-        commands.push(CommandObject {
-            text: input[j..i].trim().to_string(),
-            separator: Separator::Empty,
-            status_code: 0,
-        });
+    #[test]
+    fn last_variable_gets_the_rest() {
+        assert_eq!(split("  a  b  c  ", " \t\n", 2), ["a", "b  c"]);
+        assert_eq!(split("one", " \t\n", 3), ["one"]);
     }
 
-    return commands;
+    #[test]
+    fn array_gets_every_field() {
+        assert_eq!(split(" 1 2  3 ", " \t\n", usize::MAX), ["1", "2", "3"]);
+        assert_eq!(split("a::b:", ":", usize::MAX), ["a", "", "b"]);
+        assert!(split("", " \t\n", usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn escaped_characters_do_not_delimit() {
+        let chars = [
+            ('a', false),
+            (' ', true),
+            ('b', false),
+            (' ', false),
+            ('c', false),
+        ];
+        assert_eq!(read_split(&chars, " ", usize::MAX), ["a b", "c"]);
+    }
 }
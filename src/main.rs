@@ -1,163 +1,393 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::stdin;
 use std::io::stdout;
 use std::io::Write;
-use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::process::Child;
-use std::process::Stdio;
+use std::process::exit;
+use std::sync::atomic::Ordering;
 
-pub mod lib;
-use lib::*;
+use shell::ast::List;
+use shell::exec::{execute_list, execute_script, flush_stdout, reap_jobs, source_file};
+use shell::expand::expand_string;
+use shell::parser::{parse, ParseError};
+use shell::*;
 
-fn main() {
-    let mut env: HashMap<String, String> = save_env();
-    update_shlvl(&mut env);
+const USAGE: &str = "Usage:\tminibash [long option] [option] ...
+\tminibash [long option] [option] script-file ...
+Long options:
+\t--help
+\t--init-file file
+\t--login
+\t--noprofile
+\t--norc
+\t--posix
+\t--rcfile file
+\t--version
+Shell options:
+\t-ils or -c command\t\t(invocation only)
+\t-eux or -o option";
 
-    loop {
-        unsafe {
-            libc::signal(libc::SIGINT, handle_sigint as libc::sighandler_t);
-            libc::signal(libc::SIGUSR1, handle_sigusr1 as libc::sighandler_t);
-            libc::signal(libc::SIGQUIT, handle_sigquit as libc::sighandler_t);
-        }
+// What the command line asks the shell to do, besides the options of `set`.
+#[derive(Default)]
+struct Invocation {
+    command: bool, // -c: the first argument is a command string
+    stdin: bool,   // -s: read the commands from standard input
+    interactive: bool,
+    noprofile: bool,        // skip the login profiles
+    norc: bool,             // skip ~/.minibashrc
+    rcfile: Option<String>, // read instead of ~/.minibashrc
+    args: Vec<String>,      // the arguments left after the options
+}
 
-        let mut skip_until_semicolon = false;
+fn invalid_option(option: &str) -> ! {
+    eprintln!("minibash: {}: invalid option", option);
+    eprintln!("{}", USAGE);
+    exit(2);
+}
 
-        print!("minibash-3.2$ ");
-        if let Err(error) = stdout().flush() {
-            eprintln!("{}", error);
+// Parses the options given to minibash, applying the ones of `set` to the
+// shell as they come.
+fn parse_arguments(shell: &mut Shell) -> Invocation {
+    let mut args = std::env::args();
+    // Login programs start the shell with a '-' before its name.
+    if args.next().is_some_and(|name| name.starts_with('-')) {
+        shell.login = true;
+    }
+    let args: Vec<String> = args.collect();
+    let mut invocation = Invocation::default();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let on = arg.starts_with('-');
+        match arg {
+            "--" | "-" => {
+                i += 1;
+                break;
+            }
+            "--help" => {
+                println!("minibash, version {}", env!("CARGO_PKG_VERSION"));
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--version" => {
+                println!("minibash, version {}", env!("CARGO_PKG_VERSION"));
+                exit(0);
+            }
+            "--login" => shell.login = true,
+            "--noprofile" => invocation.noprofile = true,
+            "--norc" => invocation.norc = true,
+            "--posix" => shell.options.posix = true,
+            "--rcfile" | "--init-file" => {
+                i += 1;
+                match args.get(i) {
+                    Some(path) => invocation.rcfile = Some(path.clone()),
+                    None => {
+                        eprintln!("minibash: {}: option requires an argument", arg);
+                        exit(2);
+                    }
+                }
+            }
+            _ if arg.starts_with("--") => invalid_option(arg),
+            _ if (on || arg.starts_with('+')) && arg.len() > 1 => {
+                for letter in arg.chars().skip(1) {
+                    match letter {
+                        'c' if on => invocation.command = true,
+                        's' if on => invocation.stdin = true,
+                        'i' if on => invocation.interactive = true,
+                        'l' if on => shell.login = true,
+                        'o' => {
+                            i += 1;
+                            match args.get(i) {
+                                Some(name) if shell.options.set(name, on) => {}
+                                Some(name) => {
+                                    eprintln!("minibash: {}: invalid option name", name);
+                                    exit(2);
+                                }
+                                None => shell.options.print(!on),
+                            }
+                        }
+                        _ if shell.options.set_letter(letter, on) => {}
+                        _ => invalid_option(&format!("{}{}", &arg[..1], letter)),
+                    }
+                }
+            }
+            _ => break,
         }
+        i += 1;
+    }
+    invocation.args = args[i..].to_vec();
+    invocation
+}
 
-        let mut input = String::new();
-        stdin().read_line(&mut input).unwrap();
-        if input.is_empty() {
-            unsafe {
-                handle_sigusr1(libc::SIGUSR1);
+// Reads the startup files: the profiles for a login shell, otherwise the rc
+// file for an interactive shell, or the file named by ENV in POSIX mode.
+// Missing files are skipped.
+fn startup(shell: &mut Shell, invocation: &Invocation) {
+    let home = shell.env.get("HOME").cloned();
+    if shell.login {
+        if !invocation.noprofile {
+            source_file(shell, "/etc/profile");
+            if let Some(home) = &home {
+                source_file(shell, &format!("{}/.minibash_profile", home));
             }
         }
-        let mut input = input.trim().to_string();
-        let commands: Vec<CommandObject> = arg_split(&mut input);
-        let mut iterator = commands.iter().peekable();
-        let mut skip = false;
-        let mut previous_command: Option<Child> = None;
-
-        while let Some(command) = iterator.next() {
-            if skip_until_semicolon == true {
-                if command.separator != Separator::SemiColon
-                    && command.separator != Separator::Empty
-                {
-                    continue;
-                } else {
-                    skip_until_semicolon = false;
-                    if command.separator == Separator::Empty {
-                        break;
-                    } else {
-                        continue;
-                    }
+    } else if shell.interactive && shell.options.posix {
+        if let Some(env) = shell.env.get("ENV").cloned() {
+            let path = expand_string(shell, &env);
+            source_file(shell, &path);
+        }
+    } else if shell.interactive && !invocation.norc {
+        match &invocation.rcfile {
+            Some(path) => {
+                if source_file(shell, path).is_none() {
+                    eprintln!("minibash: {}: No such file or directory", path);
                 }
             }
-
-            if skip == true {
-                skip = false;
-                continue;
+            None => {
+                if let Some(home) = &home {
+                    source_file(shell, &format!("{}/.minibashrc", home));
+                }
             }
+        }
+    }
+    shell.flow = None;
+}
+
+// Runs the command string of -c. The argument after it becomes $0 and the
+// others the positional parameters.
+fn run_command_string(shell: &mut Shell, args: &[String]) -> ! {
+    let command = match args.first() {
+        Some(command) => command,
+        None => {
+            eprintln!("minibash: -c: option requires an argument");
+            exit(2);
+        }
+    };
+    if let Some(name) = args.get(1) {
+        shell.name = name.clone();
+        shell.positional = args[2..].to_vec();
+    }
+    let status = execute_script(shell, command, "minibash: -c");
+    flush_stdout();
+    exit(status);
+}
+
+// Shows the prompt held by a variable such as PS1.
+fn show_prompt(shell: &mut Shell, variable: &str) {
+    let text = prompt::printable(&prompt::prompt(shell, variable));
+    print!("{}", text);
+    if let Err(error) = stdout().flush() {
+        eprintln!("{}", error);
+    }
+    if let Ok(mut prompt) = PROMPT.lock() {
+        *prompt = text;
+    }
+}
+
+// Runs the script named by the first argument, with the others as its
+// positional parameters, then exits with its status.
+fn run_script(shell: &mut Shell, args: &[String]) -> ! {
+    let path = &args[0];
+    let text = match std::fs::read(path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(error) => {
+            let (message, status) = match error.kind() {
+                std::io::ErrorKind::NotFound => ("No such file or directory".to_string(), 127),
+                std::io::ErrorKind::IsADirectory => ("Is a directory".to_string(), 126),
+                _ => (error.to_string(), 126),
+            };
+            eprintln!("minibash: {}: {}", path, message);
+            exit(status);
+        }
+    };
+    shell.name = path.clone();
+    shell.positional = args[1..].to_vec();
+    shell.env.set_array("BASH_SOURCE", vec![path.clone()]);
+    let status = execute_script(shell, &text, path);
+    flush_stdout();
+    exit(status);
+}
+
+// Leaves the shell at the end of its input, with the status of the last
+// command.
+fn end_of_input(shell: &mut Shell) -> ! {
+    if shell.interactive {
+        eprintln!("exit");
+        logout(shell);
+        history::save(shell);
+    }
+    flush_stdout();
+    exit(shell.status);
+}
+
+// Reads a line of input, after the prompt when interactive. At a terminal,
+// the line is typed with the line editor.
+fn read_line(shell: &mut Shell, variable: &str) -> Option<String> {
+    let editing = shell.options.emacs || shell.options.vi;
+    if shell.interactive && editing && unsafe { libc::isatty(0) } == 1 {
+        let prompt = prompt::prompt(shell, variable);
+        return editor::read_line(shell, &prompt);
+    }
+    if shell.interactive {
+        show_prompt(shell, variable);
+    }
+    read_line_fd(0, true)
+}
 
-            let stdin = previous_command
-                .take()
-                .map_or(Stdio::inherit(), |output: Child| {
-                    Stdio::from(output.stdout.unwrap())
-                });
-
-            let input_output =
-                if command.separator == Separator::WriteRedirection && iterator.peek().is_some() {
-                    skip = true;
-                    let file = OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .truncate(true)
-                        .create(true)
-                        .open(iterator.peek().unwrap().text.clone());
-                    match file {
-                        Ok(file) => {
-                            let file_out = file.try_clone();
-                            unsafe {
-                                Some(InputOutput {
-                                    file: Some(file),
-                                    stdin: stdin,
-                                    stdout: Stdio::from_raw_fd(file_out.unwrap().into_raw_fd()),
-                                    output: None,
-                                })
+// Reads lines until they form complete commands. The input is read one byte
+// at a time, so that the commands run get the rest of it. Ctrl-C abandons the
+// lines read so far, a new line having been started already.
+fn read_commands(shell: &mut Shell, lines: &mut usize) -> Vec<List> {
+    let mut input = String::new();
+    let mut variable = "PS1";
+    loop {
+        match read_line(shell, variable) {
+            Some(line) => {
+                *lines += 1;
+                if !shell.options.histexpand {
+                    input.push_str(&line);
+                } else {
+                    // A line with a reference to the history is shown as
+                    // expanded. An error abandons the command.
+                    match history::expand(shell, &line) {
+                        Ok(None) => input.push_str(&line),
+                        Ok(Some((expanded, print))) => {
+                            print!("{}", expanded);
+                            input.push_str(&expanded);
+                            if print {
+                                history::add(shell, input.trim_end_matches('\n'));
+                                input.clear();
+                                variable = "PS1";
+                                continue;
                             }
                         }
-                        Err(_) => None,
-                    }
-                } else if command.separator == Separator::WriteAppendRedirection
-                    && iterator.peek().is_some()
-                {
-                    skip = true;
-                    let file = OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .append(true)
-                        .create(true)
-                        .open(iterator.peek().unwrap().text.clone());
-                    match file {
-                        Ok(file) => {
-                            let file_out = file.try_clone();
-                            unsafe {
-                                Some(InputOutput {
-                                    file: Some(file),
-                                    stdin: stdin,
-                                    stdout: Stdio::from_raw_fd(file_out.unwrap().into_raw_fd()),
-                                    output: None,
-                                })
-                            }
+                        Err(message) => {
+                            eprintln!("minibash: {}", message);
+                            input.clear();
+                            variable = "PS1";
+                            continue;
                         }
-                        Err(_) => None,
                     }
-                } else if command.separator == Separator::Pipe && iterator.peek().is_some() {
-                    Some(InputOutput {
-                        file: None,
-                        stdin: stdin,
-                        stdout: Stdio::piped(),
-                        output: None,
-                    })
-                } else {
-                    Some(InputOutput {
-                        file: None,
-                        stdin: stdin,
-                        stdout: Stdio::inherit(),
-                        output: None,
-                    })
-                };
-
-            let mut args = splitter(&dollar_expander(&mut env, command.text.clone()));
-            if args.is_empty() {
+                }
+            }
+            None if INTERRUPTED.swap(false, Ordering::SeqCst) => {
+                input.clear();
+                variable = "PS1";
+                shell.status = 130;
                 continue;
             }
+            None if input.is_empty() => end_of_input(shell),
+            None => {
+                if shell.interactive {
+                    eprintln!("minibash: syntax error: unexpected end of file");
+                } else {
+                    eprintln!(
+                        "minibash: line {}: syntax error: unexpected end of file",
+                        *lines + 1
+                    );
+                }
+                shell.status = 2;
+                end_of_input(shell);
+            }
+        }
 
-            let mut command = command.clone();
-
-            match input_output {
-                Some(input_output) => {
-                    previous_command =
-                        command_matcher(&mut env, &mut args, &mut command, input_output);
+        let parsed = parse(&input, expanded_aliases(shell));
+        // The lines of a command make a single entry in the history.
+        if shell.interactive && !matches!(parsed, Err(ParseError::Incomplete)) {
+            history::add(shell, input.trim_end_matches('\n'));
+        }
+        match parsed {
+            // Keep reading while a construct such as `if` is left open.
+            Err(ParseError::Incomplete) => variable = "PS2",
+            Err(ParseError::Syntax(message, line)) => {
+                shell.status = 2;
+                if shell.interactive {
+                    eprintln!("minibash: {}", message);
+                    return Vec::new();
                 }
-                None => {
-                    previous_command = None;
-                    skip_until_semicolon = true;
-                    eprintln!("Error opening file");
-                    continue;
+                // A syntax error ends a shell reading commands from a file or
+                // a pipe.
+                let first = *lines - input.lines().count();
+                eprintln!("minibash: line {}: {}", first + line, message);
+                if let Some(text) = input.lines().nth(line.saturating_sub(1)) {
+                    eprintln!("minibash: line {}: `{}'", first + line, text);
                 }
-            };
+                end_of_input(shell);
+            }
+            Ok(commands) => {
+                shell.command_number += 1;
+                return commands;
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut shell = Shell {
+        name: "minibash".to_string(),
+        env: save_env(),
+        ..Default::default()
+    };
+    update_shlvl(&mut shell.env);
+    if shell.env.get("PS4").is_none() {
+        shell.env.insert("PS4".to_string(), "+ ".to_string());
+    }
 
-            if command.separator != Separator::Pipe {
-                previous_command = None;
+    let invocation = parse_arguments(&mut shell);
+    let reads_stdin = !invocation.command && (invocation.stdin || invocation.args.is_empty());
+    shell.interactive = invocation.interactive
+        || (reads_stdin && unsafe { libc::isatty(0) == 1 && libc::isatty(2) == 1 });
+    shell.options.expand_aliases = shell.interactive;
+    if shell.interactive {
+        shell.options.emacs = true;
+        shell.options.histexpand = true;
+        let home = shell.env.get("HOME").cloned().unwrap_or_default();
+        for (name, value) in [
+            ("PS1", "\\s-\\v\\$ ".to_string()),
+            ("PS2", "> ".to_string()),
+            ("HISTFILE", format!("{}/.minibash_history", home)),
+            ("HISTSIZE", "500".to_string()),
+            ("HISTFILESIZE", "500".to_string()),
+        ] {
+            if shell.env.get(name).is_none() {
+                shell.env.insert(name.to_string(), value);
             }
+        }
+    }
+    startup(&mut shell, &invocation);
+    if shell.interactive {
+        history::load(&mut shell);
+    }
+    if invocation.command {
+        run_command_string(&mut shell, &invocation.args);
+    }
+    if !invocation.stdin && !invocation.args.is_empty() {
+        run_script(&mut shell, &invocation.args);
+    }
+    shell.positional = invocation.args;
 
-            if command.status_code != 0 && command.separator == Separator::Ampersand {
-                skip_until_semicolon = true;
+    let mut lines = 0;
+    loop {
+        if shell.interactive {
+            unsafe {
+                // No SA_RESTART, so that Ctrl-C interrupts a `read` waiting for input.
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handle_sigint as *const () as libc::sighandler_t;
+                libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+                libc::signal(
+                    libc::SIGQUIT,
+                    handle_sigquit as *const () as libc::sighandler_t,
+                );
             }
         }
+        reap_jobs(&mut shell);
+
+        let commands = read_commands(&mut shell, &mut lines);
+
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        EXECUTING.store(true, Ordering::SeqCst);
+        for command in &commands {
+            execute_list(&mut shell, command);
+        }
+        EXECUTING.store(false, Ordering::SeqCst);
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        shell.flow = None;
     }
 }
@@ -0,0 +1,254 @@
+use crate::Shell;
+
+// The options of `set`. Each one has a long name for `set -o` and, for most,
+// a letter for `set -e` and the like, which also shows in `$-`. The options
+// of `shopt` are kept alongside them.
+
+#[derive(Clone)]
+pub struct Options {
+    pub emacs: bool,          // edit the command line with the Emacs bindings
+    pub errexit: bool,        // exit when a command fails
+    pub histexpand: bool,     // expand the references to the history with !
+    pub nounset: bool,        // expanding an unset parameter is an error
+    pub posix: bool,          // follow POSIX where bash differs from it
+    pub vi: bool,             // edit the command line with the vi bindings
+    pub xtrace: bool,         // print the commands before running them
+    pub promptvars: bool,     // expand parameters and commands in the prompts
+    pub expand_aliases: bool, // expand the aliases in the commands read
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            emacs: false,
+            errexit: false,
+            histexpand: false,
+            nounset: false,
+            posix: false,
+            vi: false,
+            xtrace: false,
+            promptvars: true,
+            expand_aliases: false,
+        }
+    }
+}
+
+const OPTIONS: [(&str, Option<char>); 7] = [
+    ("emacs", None),
+    ("errexit", Some('e')),
+    ("histexpand", Some('H')),
+    ("nounset", Some('u')),
+    ("posix", None),
+    ("vi", None),
+    ("xtrace", Some('x')),
+];
+
+const SHOPT_OPTIONS: [&str; 2] = ["expand_aliases", "promptvars"];
+
+pub const USAGE: &str = "set [-Heux] [-o option-name] [--] [-] [arg ...]";
+
+impl Options {
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "emacs" => Some(&mut self.emacs),
+            "errexit" => Some(&mut self.errexit),
+            "histexpand" => Some(&mut self.histexpand),
+            "nounset" => Some(&mut self.nounset),
+            "posix" => Some(&mut self.posix),
+            "vi" => Some(&mut self.vi),
+            "xtrace" => Some(&mut self.xtrace),
+            "promptvars" => Some(&mut self.promptvars),
+            "expand_aliases" => Some(&mut self.expand_aliases),
+            _ => None,
+        }
+    }
+
+    fn is_set(&self, name: &str) -> bool {
+        match name {
+            "emacs" => self.emacs,
+            "errexit" => self.errexit,
+            "histexpand" => self.histexpand,
+            "nounset" => self.nounset,
+            "posix" => self.posix,
+            "vi" => self.vi,
+            "xtrace" => self.xtrace,
+            "promptvars" => self.promptvars,
+            "expand_aliases" => self.expand_aliases,
+            _ => false,
+        }
+    }
+
+    // Turns on or off the option of `set` with this long name. Returns false
+    // when there is no such option.
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        if !OPTIONS.iter().any(|(option, _)| *option == name) {
+            return false;
+        }
+        // Only one set of key bindings can be in use.
+        match name {
+            "emacs" if on => self.vi = false,
+            "vi" if on => self.emacs = false,
+            _ => {}
+        }
+        match self.flag(name) {
+            Some(flag) => {
+                *flag = on;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Turns on or off the option with this letter.
+    pub fn set_letter(&mut self, letter: char, on: bool) -> bool {
+        match OPTIONS.iter().find(|(_, c)| *c == Some(letter)) {
+            Some((name, _)) => self.set(name, on),
+            None => false,
+        }
+    }
+
+    // The letters of the options that are on, as `$-` shows them.
+    pub fn letters(&self) -> String {
+        OPTIONS
+            .iter()
+            .filter(|(name, _)| self.is_set(name))
+            .filter_map(|(_, letter)| *letter)
+            .collect()
+    }
+
+    // Lists the options the way `set -o` does, or as commands that restore
+    // them for `set +o`.
+    pub fn print(&self, commands: bool) {
+        for (name, _) in OPTIONS {
+            let on = self.is_set(name);
+            if commands {
+                println!("set {}o {}", if on { '-' } else { '+' }, name);
+            } else {
+                println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+            }
+        }
+    }
+}
+
+// Handles the options at the start of the arguments of `set`. Returns the
+// index of the first positional parameter, and whether the positional
+// parameters are to be replaced, or the status of an invalid option.
+pub fn parse_set_options(shell: &mut Shell, args: &[String]) -> Result<(usize, bool), i32> {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        let on = arg.starts_with('-');
+        if arg == "--" {
+            return Ok((i + 1, true));
+        }
+        if arg == "-" {
+            // Ends the options, turning off tracing as the historical shells did.
+            shell.options.xtrace = false;
+            return Ok((i + 1, i + 1 < args.len()));
+        }
+        if !(on || arg.starts_with('+')) || arg.len() == 1 {
+            break;
+        }
+        for letter in arg.chars().skip(1) {
+            if letter == 'o' {
+                i += 1;
+                match args.get(i) {
+                    None => shell.options.print(!on),
+                    Some(name) => {
+                        if !shell.options.set(name, on) {
+                            eprintln!("minibash: set: {}: invalid option name", name);
+                            return Err(2);
+                        }
+                    }
+                }
+            } else if !shell.options.set_letter(letter, on) {
+                eprintln!("minibash: set: {}{}: invalid option", &arg[..1], letter);
+                eprintln!("set: usage: {}", USAGE);
+                return Err(2);
+            }
+        }
+        i += 1;
+    }
+    Ok((i, i < args.len()))
+}
+
+fn print_shopt(name: &str, on: bool, commands: bool) {
+    if commands {
+        println!("shopt {} {}", if on { "-s" } else { "-u" }, name);
+    } else {
+        println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+    }
+}
+
+// Sets (-s) or unsets (-u) the options of `shopt`, or shows them, in a form
+// that can be read back with -p. With -q, only the status tells whether
+// they are all on.
+pub fn shopt_handler(shell: &mut Shell, args: &[String]) -> i32 {
+    let mut change: Option<bool> = None;
+    let mut commands = false;
+    let mut quiet = false;
+    let mut i = 0;
+    while let Some(arg) = args
+        .get(i)
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        for letter in arg.chars().skip(1) {
+            match letter {
+                's' => change = Some(true),
+                'u' => change = Some(false),
+                'p' => commands = true,
+                'q' => quiet = true,
+                _ => {
+                    eprintln!("minibash: shopt: -{}: invalid option", letter);
+                    eprintln!("shopt: usage: shopt [-pqsu] [optname ...]");
+                    return 2;
+                }
+            }
+        }
+        i += 1;
+    }
+    let names = &args[i..];
+    if let Some(name) = names
+        .iter()
+        .find(|name| !SHOPT_OPTIONS.contains(&name.as_str()))
+    {
+        eprintln!("minibash: shopt: {}: invalid shell option name", name);
+        return 1;
+    }
+    if let Some(on) = change {
+        for name in names {
+            if let Some(flag) = shell.options.flag(name) {
+                *flag = on;
+            }
+        }
+        if names.is_empty() {
+            for name in SHOPT_OPTIONS {
+                let set = shell.options.is_set(name);
+                if set == on {
+                    print_shopt(name, set, commands);
+                }
+            }
+        }
+        return 0;
+    }
+    let names: Vec<&str> = if names.is_empty() {
+        SHOPT_OPTIONS.to_vec()
+    } else {
+        names.iter().map(|name| name.as_str()).collect()
+    };
+    let mut status = 0;
+    for name in names {
+        let on = shell.options.is_set(name);
+        if !quiet {
+            print_shopt(name, on, commands);
+        }
+        if !on {
+            status = 1;
+        }
+    }
+    status
+}
@@ -0,0 +1,1020 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::test;
+
+#[derive(Debug)]
+pub enum ParseError {
+    Incomplete,            // the input ended in the middle of a command
+    Syntax(String, usize), // message and line number
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Operator(&'static str),
+    IoNumber(i32),
+    Newline,
+    Eof,
+}
+
+// Longest operators first so that ">>" is not read as two ">".
+const OPERATORS: [&str; 20] = [
+    "<<<", "<<-", "<<", ";;&", "&&", "||", ";;", ";&", ">>", "<&", ">&", "<>", ">|", "&", "|", ";",
+    "<", ">", "(", ")",
+];
+
+// Reserved words that end a compound list and can never start a command.
+const TERMINATORS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+fn is_metachar(c: char) -> bool {
+    matches!(
+        c,
+        ' ' | '\t' | '\n' | ';' | '&' | '|' | '<' | '>' | '(' | ')'
+    )
+}
+
+// Function names are more permissive than variable names, as in bash.
+fn is_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.chars().all(|c| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-.:@+".contains(c))
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+fn redirect_op(op: &str) -> Option<(i32, RedirectOp)> {
+    match op {
+        "<" => Some((0, RedirectOp::Read)),
+        ">" => Some((1, RedirectOp::Write)),
+        ">|" => Some((1, RedirectOp::Clobber)),
+        ">>" => Some((1, RedirectOp::Append)),
+        "<>" => Some((0, RedirectOp::ReadWrite)),
+        "<&" => Some((0, RedirectOp::DupRead)),
+        ">&" => Some((1, RedirectOp::DupWrite)),
+        "<<<" => Some((0, RedirectOp::HereString)),
+        "<<" | "<<-" => Some((0, RedirectOp::HereDocument)),
+        _ => None,
+    }
+}
+
+fn token_name(token: &Token) -> String {
+    match token {
+        Token::Word(word) => word.clone(),
+        Token::Operator(op) => op.to_string(),
+        Token::IoNumber(n) => n.to_string(),
+        Token::Newline => "newline".to_string(),
+        Token::Eof => "EOF".to_string(),
+    }
+}
+
+// Removes the quotes of the delimiter of a here-document.
+pub fn unquote(word: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {}
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+// Aliases are expanded as the input is parsed: the value of an alias
+// replaces its name in the text, which is then read again.
+#[derive(Clone)]
+pub struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    aliases: Option<Rc<HashMap<String, String>>>, // None when aliases are not expanded
+    expansions: Vec<(String, usize)>, // aliases being expanded and where their values end
+    alias_next: Option<usize>,        // end of a value ending with a blank
+    documents_end: Option<usize>,     // end of the here-documents after this line
+    continued: bool,                  // the blanks skipped ended with a backslash-newline
+}
+
+impl Parser {
+    pub fn new(input: &str, aliases: Option<Rc<HashMap<String, String>>>) -> Parser {
+        Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+            line: 1,
+            aliases,
+            expansions: Vec::new(),
+            alias_next: None,
+            documents_end: None,
+            continued: false,
+        }
+    }
+
+    // Changes the aliases expanded in the commands not parsed yet.
+    pub fn set_aliases(&mut self, aliases: Option<Rc<HashMap<String, String>>>) {
+        self.aliases = aliases;
+    }
+
+    // Parses the next complete command, which ends at a newline or at the end
+    // of the input. Returns None once the input is exhausted.
+    pub fn next_command(&mut self) -> Result<Option<List>, ParseError> {
+        self.skip_newlines()?;
+        if self.peek()? == Token::Eof {
+            return Ok(None);
+        }
+        let list = self.complete_command()?;
+        match self.next_token()? {
+            Token::Newline | Token::Eof => Ok(Some(list)),
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn unexpected(&self, token: &Token) -> ParseError {
+        if *token == Token::Eof {
+            return ParseError::Incomplete;
+        }
+        ParseError::Syntax(
+            format!("syntax error near unexpected token `{}'", token_name(token)),
+            self.line,
+        )
+    }
+
+    fn skip_blanks(&mut self) {
+        self.continued = false;
+        while self.pos < self.chars.len() {
+            match self.chars[self.pos] {
+                ' ' | '\t' => self.pos += 1,
+                '\\' if self.chars.get(self.pos + 1) == Some(&'\n') => {
+                    self.pos += 2;
+                    self.line += 1;
+                    self.continued = true;
+                    continue;
+                }
+                '#' => {
+                    while self.pos < self.chars.len() && self.chars[self.pos] != '\n' {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+            self.continued = false;
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_blanks();
+        if self.pos == self.chars.len() {
+            // A backslash-newline at the end continues the line.
+            if self.continued {
+                return Err(ParseError::Incomplete);
+            }
+            return Ok(Token::Eof);
+        }
+        if self.chars[self.pos] == '\n' {
+            self.pos += 1;
+            self.line += 1;
+            // The here-documents of the line were read with their redirects.
+            if let Some(end) = self.documents_end.take() {
+                self.line += self.chars[self.pos..end]
+                    .iter()
+                    .filter(|&&c| c == '\n')
+                    .count();
+                self.pos = end;
+            }
+            return Ok(Token::Newline);
+        }
+        if self.starts_with("<(") || self.starts_with(">(") {
+            return self.read_word().map(Token::Word);
+        }
+        for op in OPERATORS {
+            if self.starts_with(op) {
+                self.pos += op.len();
+                return Ok(Token::Operator(op));
+            }
+        }
+
+        let mut end = self.pos;
+        while end < self.chars.len() && self.chars[end].is_ascii_digit() {
+            end += 1;
+        }
+        if end > self.pos && end < self.chars.len() && matches!(self.chars[end], '<' | '>') {
+            let digits: String = self.chars[self.pos..end].iter().collect();
+            if let Ok(fd) = digits.parse() {
+                self.pos = end;
+                return Ok(Token::IoNumber(fd));
+            }
+        }
+
+        self.read_word().map(Token::Word)
+    }
+
+    // Replaces the word at the current position by the value of its alias,
+    // unless the text comes from the expansion of that same alias. The first
+    // word of the value is checked in turn, and a value ending with a blank
+    // makes the word after it a candidate too.
+    fn expand_alias(&mut self) {
+        loop {
+            self.skip_blanks();
+            let pos = self.pos;
+            self.expansions.retain(|(_, end)| *end > pos);
+            let line = self.line;
+            let word = self.read_word();
+            let end = self.pos;
+            self.pos = pos;
+            self.line = line;
+            let word = match word {
+                Ok(word) if !self.expansions.iter().any(|(name, _)| *name == word) => word,
+                _ => return,
+            };
+            let value: Vec<char> = match self.aliases.as_ref().and_then(|a| a.get(&word)) {
+                Some(value) => value.chars().collect(),
+                None => return,
+            };
+            let length = value.len();
+            self.chars.splice(pos..end, value);
+            // The text after the word moves by the difference in length.
+            let shift = |at: &mut usize| *at = *at + length - (end - pos);
+            self.expansions.iter_mut().for_each(|(_, at)| shift(at));
+            self.alias_next.as_mut().filter(|at| **at > pos).map(shift);
+            self.expansions.push((word, pos + length));
+            if length > 0 && matches!(self.chars[pos + length - 1], ' ' | '\t') {
+                self.alias_next = Some(pos + length);
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Token, ParseError> {
+        let (pos, line, documents_end) = (self.pos, self.line, self.documents_end);
+        let token = self.next_token();
+        self.pos = pos;
+        self.line = line;
+        self.documents_end = documents_end;
+        token
+    }
+
+    // Reads the text of a here-document. It starts on the line after the
+    // current one, or after the here-document before it on the same line, and
+    // ends with a line holding only the delimiter. With <<-, the tabs at the
+    // start of the lines are removed.
+    fn here_document(&mut self, delimiter: &str, strip: bool) -> Result<String, ParseError> {
+        let mut pos = match self.documents_end {
+            Some(end) => end,
+            None => {
+                let mut scanner = self.clone();
+                loop {
+                    match scanner.next_token()? {
+                        Token::Newline => break scanner.pos,
+                        Token::Eof => return Err(ParseError::Incomplete),
+                        _ => {}
+                    }
+                }
+            }
+        };
+        let delimiter = unquote(delimiter);
+        let mut document = String::new();
+        loop {
+            // The last line of the input may have no newline.
+            let (end, last) = match self.chars[pos..].iter().position(|&c| c == '\n') {
+                Some(end) => (pos + end, false),
+                None => (self.chars.len(), true),
+            };
+            let mut line: String = self.chars[pos..end].iter().collect();
+            if strip {
+                line = line.trim_start_matches('\t').to_string();
+            }
+            pos = (end + 1).min(self.chars.len());
+            if line == delimiter {
+                break;
+            }
+            if last {
+                return Err(ParseError::Incomplete);
+            }
+            document.push_str(&line);
+            document.push('\n');
+        }
+        self.documents_end = Some(pos);
+        Ok(document)
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.chars[self.pos..].starts_with(&text)
+    }
+
+    fn advance(&mut self) -> Result<char, ParseError> {
+        match self.chars.get(self.pos) {
+            Some(&c) => {
+                self.pos += 1;
+                if c == '\n' {
+                    self.line += 1;
+                }
+                Ok(c)
+            }
+            None => Err(ParseError::Incomplete),
+        }
+    }
+
+    // Reads a word up to the next unquoted metacharacter. Quotes and nested
+    // substitutions are skipped over and kept in the raw text.
+    fn read_word(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        while self.pos < self.chars.len() {
+            // Process substitutions are part of the word they are found in.
+            if matches!(self.chars[self.pos], '<' | '>')
+                && self.chars.get(self.pos + 1) == Some(&'(')
+            {
+                self.advance()?;
+                self.skip_dollar()?;
+                continue;
+            }
+            if is_metachar(self.chars[self.pos]) {
+                break;
+            }
+            match self.advance()? {
+                // A backslash-newline at the end continues the word.
+                '\\' if self.chars[self.pos..] == ['\n'] => return Err(ParseError::Incomplete),
+                '\\' if self.pos < self.chars.len() => {
+                    self.advance()?;
+                }
+                '\'' => while self.advance()? != '\'' {},
+                '"' => self.skip_double_quotes()?,
+                '`' => self.skip_backquotes()?,
+                '$' => self.skip_dollar()?,
+                _ => {}
+            }
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn skip_double_quotes(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.advance()? {
+                '"' => return Ok(()),
+                '\\' => {
+                    self.advance()?;
+                }
+                '`' => self.skip_backquotes()?,
+                '$' => self.skip_dollar()?,
+                _ => {}
+            }
+        }
+    }
+
+    fn skip_backquotes(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.advance()? {
+                '`' => return Ok(()),
+                '\\' => {
+                    self.advance()?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Skips the body of $(...), $((...)) and ${...} after a '$'.
+    fn skip_dollar(&mut self) -> Result<(), ParseError> {
+        let close = match self.chars.get(self.pos) {
+            Some('(') => ')',
+            Some('{') => '}',
+            _ => return Ok(()),
+        };
+        let open = self.advance()?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.advance()? {
+                '\\' => {
+                    self.advance()?;
+                }
+                '\'' if close == ')' => while self.advance()? != '\'' {},
+                '"' => self.skip_double_quotes()?,
+                '`' => self.skip_backquotes()?,
+                '$' => self.skip_dollar()?,
+                c if c == open => depth += 1,
+                c if c == close => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while self.peek()? == Token::Newline {
+            self.next_token()?;
+        }
+        Ok(())
+    }
+
+    fn expect_operator(&mut self, op: &str) -> Result<(), ParseError> {
+        match self.next_token()? {
+            Token::Operator(found) if found == op => Ok(()),
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        match self.next_token()? {
+            Token::Word(w) if w == word => Ok(()),
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn complete_command(&mut self) -> Result<List, ParseError> {
+        let mut items: Vec<ListItem> = Vec::new();
+        loop {
+            let and_or = self.and_or()?;
+            let token = self.peek()?;
+            let background = token == Token::Operator("&");
+            items.push(ListItem { and_or, background });
+            if !background && token != Token::Operator(";") {
+                break;
+            }
+            self.next_token()?;
+            if matches!(self.peek()?, Token::Newline | Token::Eof) {
+                break;
+            }
+        }
+        Ok(List { items })
+    }
+
+    fn ends_compound_list(&self, token: &Token) -> Result<bool, ParseError> {
+        match token {
+            Token::Eof => Err(ParseError::Incomplete),
+            Token::Word(word) => Ok(TERMINATORS.contains(&word.as_str())),
+            Token::Operator(op) => Ok(matches!(*op, ")" | ";;" | ";&" | ";;&")),
+            _ => Ok(false),
+        }
+    }
+
+    // A list inside a compound command, ended by a reserved word such as
+    // `then` or `fi` that the caller then consumes.
+    fn compound_list(&mut self) -> Result<List, ParseError> {
+        let mut items: Vec<ListItem> = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            let token = self.peek()?;
+            if self.ends_compound_list(&token)? {
+                if items.is_empty() {
+                    return Err(self.unexpected(&token));
+                }
+                break;
+            }
+            let and_or = self.and_or()?;
+            match self.peek()? {
+                Token::Operator(";") | Token::Newline => {
+                    self.next_token()?;
+                    items.push(ListItem {
+                        and_or,
+                        background: false,
+                    });
+                }
+                Token::Operator("&") => {
+                    self.next_token()?;
+                    items.push(ListItem {
+                        and_or,
+                        background: true,
+                    });
+                }
+                _ => {
+                    items.push(ListItem {
+                        and_or,
+                        background: false,
+                    });
+                    break;
+                }
+            }
+        }
+        Ok(List { items })
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.pipeline()?;
+        let mut rest: Vec<(Connector, Pipeline)> = Vec::new();
+        loop {
+            let connector = match self.peek()? {
+                Token::Operator("&&") => Connector::And,
+                Token::Operator("||") => Connector::Or,
+                _ => break,
+            };
+            self.next_token()?;
+            self.skip_newlines()?;
+            rest.push((connector, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek()? == Token::Word("!".to_string());
+        if negated {
+            self.next_token()?;
+        }
+        let mut commands = vec![self.command()?];
+        while self.peek()? == Token::Operator("|") {
+            self.next_token()?;
+            self.skip_newlines()?;
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        self.expand_alias();
+        let token = self.peek()?;
+        let compound = match &token {
+            Token::Word(word) if word == "function" => {
+                self.next_token()?;
+                let name = match self.next_token()? {
+                    Token::Word(name) if is_function_name(&name) => name,
+                    token => return Err(self.unexpected(&token)),
+                };
+                if self.peek()? == Token::Operator("(") {
+                    self.next_token()?;
+                    self.expect_operator(")")?;
+                }
+                return self.function_body(name);
+            }
+            Token::Operator("(") => {
+                self.next_token()?;
+                let list = self.compound_list()?;
+                self.expect_operator(")")?;
+                CompoundCommand::Subshell(list)
+            }
+            Token::Word(word) if word == "[[" => {
+                self.next_token()?;
+                let expression = self.cond_or()?;
+                self.expect_word("]]")?;
+                CompoundCommand::Conditional(expression)
+            }
+            Token::Word(word) if word == "{" => {
+                self.next_token()?;
+                let list = self.compound_list()?;
+                self.expect_word("}")?;
+                CompoundCommand::BraceGroup(list)
+            }
+            Token::Word(word) if word == "if" => {
+                self.next_token()?;
+                CompoundCommand::If(self.if_clause()?)
+            }
+            Token::Word(word) if word == "for" => {
+                self.next_token()?;
+                CompoundCommand::For(self.for_clause()?)
+            }
+            Token::Word(word) if word == "case" => {
+                self.next_token()?;
+                CompoundCommand::Case(self.case_clause()?)
+            }
+            Token::Word(word) if word == "while" || word == "until" => {
+                self.next_token()?;
+                CompoundCommand::While(WhileClause {
+                    until: word == "until",
+                    condition: self.compound_list()?,
+                    body: self.do_group()?,
+                })
+            }
+            Token::Word(word) if TERMINATORS.contains(&word.as_str()) => {
+                return Err(self.unexpected(&token));
+            }
+            Token::Word(word) if is_function_name(word) => {
+                let (pos, line) = (self.pos, self.line);
+                self.next_token()?;
+                if self.peek()? == Token::Operator("(") {
+                    self.next_token()?;
+                    self.expect_operator(")")?;
+                    return self.function_body(word.clone());
+                }
+                self.pos = pos;
+                self.line = line;
+                return self.simple_command().map(Command::Simple);
+            }
+            _ => return self.simple_command().map(Command::Simple),
+        };
+        let mut redirects: Vec<Redirect> = Vec::new();
+        while let Some(redirect) = self.redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(Command::Compound(compound, redirects))
+    }
+
+    // The expression of `[[ ]]`, where `&&` binds tighter than `||`.
+    fn cond_or(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expression = self.cond_and()?;
+        while self.peek()? == Token::Operator("||") {
+            self.next_token()?;
+            self.skip_newlines()?;
+            expression = CondExpr::Or(Box::new(expression), Box::new(self.cond_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn cond_and(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expression = self.cond_term()?;
+        while self.peek()? == Token::Operator("&&") {
+            self.next_token()?;
+            self.skip_newlines()?;
+            expression = CondExpr::And(Box::new(expression), Box::new(self.cond_term()?));
+        }
+        Ok(expression)
+    }
+
+    fn cond_term(&mut self) -> Result<CondExpr, ParseError> {
+        let token = self.next_token()?;
+        match token {
+            Token::Word(word) if word == "!" => Ok(CondExpr::Not(Box::new(self.cond_term()?))),
+            Token::Operator("(") => {
+                let expression = self.cond_or()?;
+                self.expect_operator(")")?;
+                Ok(CondExpr::Group(Box::new(expression)))
+            }
+            Token::Word(ref word) if word == "]]" => Err(self.unexpected(&token)),
+            Token::Word(op) if test::is_unary(&op) => match self.next_token()? {
+                Token::Word(operand) if operand != "]]" => Ok(CondExpr::Unary(op, operand)),
+                Token::Eof => Err(ParseError::Incomplete),
+                token => Err(ParseError::Syntax(
+                    format!(
+                        "unexpected argument `{}' to conditional unary operator",
+                        token_name(&token)
+                    ),
+                    self.line,
+                )),
+            },
+            Token::Word(left) => {
+                let op = match self.peek()? {
+                    Token::Word(op) if test::is_binary(&op) || op == "=~" => op,
+                    Token::Operator(op @ ("<" | ">")) => op.to_string(),
+                    _ => return Ok(CondExpr::Word(left)),
+                };
+                self.next_token()?;
+                let right = if op == "=~" {
+                    self.regex_word()?
+                } else {
+                    match self.next_token()? {
+                        Token::Word(right) if right != "]]" => right,
+                        Token::Eof => return Err(ParseError::Incomplete),
+                        _ => {
+                            return Err(ParseError::Syntax(
+                                "unexpected argument to conditional binary operator".to_string(),
+                                self.line,
+                            ))
+                        }
+                    }
+                };
+                Ok(CondExpr::Binary(left, op, right))
+            }
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    // The right side of `=~`, which may contain parentheses and `|` without
+    // quotes, as regular expressions do.
+    fn regex_word(&mut self) -> Result<String, ParseError> {
+        self.skip_blanks();
+        let start = self.pos;
+        let mut depth = 0;
+        while self.pos < self.chars.len() {
+            match self.chars[self.pos] {
+                ' ' | '\t' | '\n' | ';' | '&' if depth == 0 => break,
+                ')' if depth == 0 => break,
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            match self.advance()? {
+                '\\' if self.pos < self.chars.len() => {
+                    self.advance()?;
+                }
+                '\'' => while self.advance()? != '\'' {},
+                '"' => self.skip_double_quotes()?,
+                '`' => self.skip_backquotes()?,
+                '$' => self.skip_dollar()?,
+                _ => {}
+            }
+        }
+        if self.pos == start {
+            let token = self.peek()?;
+            return Err(self.unexpected(&token));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    // The body of a function is any compound command, with the redirections
+    // that apply on every call.
+    fn function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines()?;
+        match self.command()? {
+            body @ Command::Compound(..) => Ok(Command::FunctionDef(name, Rc::new(body))),
+            _ => Err(ParseError::Syntax(
+                format!(
+                    "syntax error: `{}': function body must be a compound command",
+                    name
+                ),
+                self.line,
+            )),
+        }
+    }
+
+    // Parses a redirection if the next token starts one.
+    fn redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let (fd, op, strip) = match self.peek()? {
+            Token::IoNumber(fd) => {
+                self.next_token()?;
+                match self.next_token()? {
+                    Token::Operator(op) => match redirect_op(op) {
+                        Some((_, redirect)) => (fd, redirect, op == "<<-"),
+                        None => return Err(self.unexpected(&Token::Operator(op))),
+                    },
+                    token => return Err(self.unexpected(&token)),
+                }
+            }
+            Token::Operator(op) => match redirect_op(op) {
+                Some((fd, redirect)) => {
+                    self.next_token()?;
+                    (fd, redirect, op == "<<-")
+                }
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let target = match self.next_token()? {
+            Token::Word(target) => target,
+            token => return Err(self.unexpected(&token)),
+        };
+        let document = match op {
+            RedirectOp::HereDocument => Some(self.here_document(&target, strip)?),
+            _ => None,
+        };
+        Ok(Some(Redirect {
+            fd,
+            op,
+            target,
+            document,
+        }))
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand {
+            assignments: Vec::new(),
+            words: Vec::new(),
+            redirects: Vec::new(),
+        };
+        loop {
+            if let Some(redirect) = self.redirect()? {
+                command.redirects.push(redirect);
+                continue;
+            }
+            self.skip_blanks();
+            if self.alias_next.is_some_and(|end| self.pos >= end) {
+                self.alias_next = None;
+                self.expand_alias();
+            }
+            match self.peek()? {
+                Token::Word(word) => {
+                    self.next_token()?;
+                    match word.find('=') {
+                        Some(i) if command.words.is_empty() && is_name(&word[..i]) => {
+                            command.assignments.push(Assignment {
+                                name: word[..i].to_string(),
+                                value: word[i + 1..].to_string(),
+                            })
+                        }
+                        _ => command.words.push(word),
+                    }
+                }
+                _ => break,
+            }
+        }
+        if command.assignments.is_empty()
+            && command.words.is_empty()
+            && command.redirects.is_empty()
+        {
+            let token = self.peek()?;
+            return Err(self.unexpected(&token));
+        }
+        Ok(command)
+    }
+
+    fn if_clause(&mut self) -> Result<IfClause, ParseError> {
+        let mut clause = IfClause {
+            branches: Vec::new(),
+            otherwise: None,
+        };
+        loop {
+            let condition = self.compound_list()?;
+            self.expect_word("then")?;
+            let body = self.compound_list()?;
+            clause.branches.push((condition, body));
+            match self.next_token()? {
+                Token::Word(word) if word == "elif" => continue,
+                Token::Word(word) if word == "else" => {
+                    clause.otherwise = Some(self.compound_list()?);
+                    self.expect_word("fi")?;
+                    break;
+                }
+                Token::Word(word) if word == "fi" => break,
+                token => return Err(self.unexpected(&token)),
+            }
+        }
+        Ok(clause)
+    }
+
+    fn for_clause(&mut self) -> Result<ForClause, ParseError> {
+        let name = match self.next_token()? {
+            Token::Word(name) if is_name(&name) => name,
+            Token::Word(name) => {
+                return Err(ParseError::Syntax(
+                    format!("`{}': not a valid identifier", name),
+                    self.line,
+                ))
+            }
+            token => return Err(self.unexpected(&token)),
+        };
+        self.skip_newlines()?;
+
+        let mut words: Option<Vec<String>> = None;
+        match self.peek()? {
+            Token::Word(word) if word == "in" => {
+                self.next_token()?;
+                let mut list: Vec<String> = Vec::new();
+                loop {
+                    match self.next_token()? {
+                        Token::Word(word) => list.push(word),
+                        Token::Operator(";") | Token::Newline => break,
+                        token => return Err(self.unexpected(&token)),
+                    }
+                }
+                words = Some(list);
+            }
+            Token::Operator(";") => {
+                self.next_token()?;
+            }
+            _ => {}
+        }
+        self.skip_newlines()?;
+
+        Ok(ForClause {
+            name,
+            words,
+            body: self.do_group()?,
+        })
+    }
+
+    fn case_clause(&mut self) -> Result<CaseClause, ParseError> {
+        let word = match self.next_token()? {
+            Token::Word(word) => word,
+            token => return Err(self.unexpected(&token)),
+        };
+        self.skip_newlines()?;
+        self.expect_word("in")?;
+
+        let mut items: Vec<CaseItem> = Vec::new();
+        loop {
+            self.skip_newlines()?;
+            match self.peek()? {
+                Token::Word(word) if word == "esac" => {
+                    self.next_token()?;
+                    break;
+                }
+                Token::Operator("(") => {
+                    self.next_token()?;
+                }
+                _ => {}
+            }
+
+            let mut patterns: Vec<String> = Vec::new();
+            loop {
+                match self.next_token()? {
+                    Token::Word(pattern) => patterns.push(pattern),
+                    token => return Err(self.unexpected(&token)),
+                }
+                match self.next_token()? {
+                    Token::Operator("|") => continue,
+                    Token::Operator(")") => break,
+                    token => return Err(self.unexpected(&token)),
+                }
+            }
+
+            self.skip_newlines()?;
+            let body = match self.peek()? {
+                Token::Operator(";;") | Token::Operator(";&") | Token::Operator(";;&") => {
+                    List { items: Vec::new() }
+                }
+                Token::Word(word) if word == "esac" => List { items: Vec::new() },
+                _ => self.compound_list()?,
+            };
+            let terminator = match self.next_token()? {
+                Token::Operator(";;") => CaseTerminator::Break,
+                Token::Operator(";&") => CaseTerminator::FallThrough,
+                Token::Operator(";;&") => CaseTerminator::Continue,
+                Token::Word(word) if word == "esac" => {
+                    items.push(CaseItem {
+                        patterns,
+                        body,
+                        terminator: CaseTerminator::Break,
+                    });
+                    break;
+                }
+                token => return Err(self.unexpected(&token)),
+            };
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
+        }
+        Ok(CaseClause { word, items })
+    }
+
+    fn do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_word("do")?;
+        let body = self.compound_list()?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+}
+
+// Returns the index just past the $(...) or ${...} whose opening character is
+// at chars[start], as the expansion of a word needs it.
+pub fn substitution_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut parser = Parser {
+        chars: chars.to_vec(),
+        pos: start,
+        ..Parser::new("", None)
+    };
+    parser.skip_dollar().ok()?;
+    Some(parser.pos)
+}
+
+// Returns the index just past the backquote closing the one at chars[start].
+pub fn backquote_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut parser = Parser {
+        chars: chars.to_vec(),
+        pos: start + 1,
+        ..Parser::new("", None)
+    };
+    parser.skip_backquotes().ok()?;
+    Some(parser.pos)
+}
+
+// Parses a whole input into its complete commands.
+pub fn parse(
+    input: &str,
+    aliases: Option<Rc<HashMap<String, String>>>,
+) -> Result<Vec<List>, ParseError> {
+    let mut parser = Parser::new(input, aliases);
+    let mut commands: Vec<List> = Vec::new();
+    while let Some(command) = parser.next_command()? {
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(input: &str, strip: bool) -> Result<String, ParseError> {
+        Parser::new(input, None).here_document("EOF", strip)
+    }
+
+    #[test]
+    fn here_documents_end_with_their_delimiter() {
+        assert_eq!(
+            document("cat <<EOF\nhi\nEOF\necho\n", false).unwrap(),
+            "hi\n"
+        );
+        assert_eq!(document("cat <<-EOF\n\thi\n\tEOF\n", true).unwrap(), "hi\n");
+        assert_eq!(document("cat <<EOF\nEOF \nEOF\n", false).unwrap(), "EOF \n");
+    }
+
+    #[test]
+    fn delimiter_may_end_the_input_without_a_newline() {
+        assert_eq!(document("cat <<EOF\nhi\nEOF", false).unwrap(), "hi\n");
+        assert!(parse("cat <<EOF\nhi\nEOF", None).is_ok());
+        assert!(parse("cat <<'EOF'; echo\n\nEOF", None).is_ok());
+    }
+
+    #[test]
+    fn missing_delimiter_is_incomplete() {
+        assert!(matches!(
+            document("cat <<EOF\nhi\nEO", false),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            document("cat <<EOF\nhi\n", false),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            document("cat <<EOF", false),
+            Err(ParseError::Incomplete)
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+use std::fs;
+
+// Patterns use `*`, `?` and `[...]`. A backslash makes the character after it
+// literal, which is how quoted parts of a word are kept out of the matching.
+
+pub fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => c.is_ascii_graphic(),
+        "lower" => c.is_lowercase(),
+        "print" => c.is_ascii_graphic() || c == ' ',
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+// Matches c against the bracket expression starting at pattern[start], which
+// is a '['. Returns whether it matched and the index after the closing ']', or
+// None when the bracket is not closed and stands for a literal '['.
+fn bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let mut low = *pattern.get(i)?;
+        if low == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if low == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= class_matches(&rest[..end], c);
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+        if low == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            low = pattern[i];
+        }
+        i += 1;
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|&high| high != ']') {
+            let mut high = pattern[i + 1];
+            i += 2;
+            if high == '\\' && i < pattern.len() {
+                high = pattern[i];
+                i += 1;
+            }
+            matched |= low <= c && c <= high;
+        } else {
+            matched |= low == c;
+        }
+    }
+}
+
+// Matches the pattern element at pattern[i], which is not a '*', against c.
+// Returns the index of the next element on success.
+fn single(pattern: &[char], i: usize, c: char) -> Option<usize> {
+    match pattern[i] {
+        '?' => Some(i + 1),
+        '[' => match bracket(pattern, i, c) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            None => (c == '[').then_some(i + 1),
+        },
+        '\\' if i + 1 < pattern.len() => (pattern[i + 1] == c).then_some(i + 2),
+        literal => (literal == c).then_some(i + 1),
+    }
+}
+
+pub fn pattern_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // last '*' and where it started
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+            continue;
+        }
+        if p < pattern.len() {
+            if let Some(next) = single(&pattern, p, text[t]) {
+                p = next;
+                t += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+// Whether the pattern has an unescaped special character.
+pub fn has_glob(pattern: &str) -> bool {
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '*' || c == '?' || c == '[' {
+            return true;
+        }
+    }
+    false
+}
+
+// Removes the backslashes that escape characters in a pattern.
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                text.push(next);
+            }
+        } else {
+            text.push(c);
+        }
+    }
+    text
+}
+
+fn join(directory: &str, name: &str) -> String {
+    if directory.is_empty() {
+        name.to_string()
+    } else if directory.ends_with('/') {
+        format!("{}{}", directory, name)
+    } else {
+        format!("{}/{}", directory, name)
+    }
+}
+
+// Expands a pattern into the sorted list of paths it matches. Names starting
+// with a dot only match when the pattern spells the dot out.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let mut paths: Vec<String> = vec![if pattern.starts_with('/') {
+        "/".to_string()
+    } else {
+        String::new()
+    }];
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let only_directories = pattern.ends_with('/');
+
+    for component in components {
+        let mut next: Vec<String> = Vec::new();
+        if !has_glob(component) {
+            let name = unescape(component);
+            for path in &paths {
+                let path = join(path, &name);
+                if fs::symlink_metadata(&path).is_ok() {
+                    next.push(path);
+                }
+            }
+        } else {
+            for path in &paths {
+                let directory = if path.is_empty() { "." } else { path.as_str() };
+                let entries = match fs::read_dir(directory) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+                let mut names: Vec<String> = entries
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                    .filter(|name| pattern_match(component, name))
+                    .collect();
+                names.sort();
+                next.extend(names.iter().map(|name| join(path, name)));
+            }
+        }
+        paths = next;
+    }
+
+    if only_directories {
+        paths = paths
+            .into_iter()
+            .filter(|path| fs::metadata(path).is_ok_and(|m| m.is_dir()))
+            .map(|path| path + "/")
+            .collect();
+    }
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(pattern_match("*", ""));
+        assert!(pattern_match("a*c", "abbbc"));
+        assert!(pattern_match("a*b*c", "aXbYbZc"));
+        assert!(!pattern_match("a*c", "abcd"));
+        assert!(pattern_match("?.rs", "a.rs"));
+        assert!(!pattern_match("?.rs", ".rs"));
+        assert!(pattern_match("**a", "bba"));
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert!(pattern_match("[abc]x", "bx"));
+        assert!(pattern_match("[a-c][!a-c]", "cd"));
+        assert!(!pattern_match("[^a-c]", "b"));
+        assert!(pattern_match("[]x]", "]"));
+        assert!(pattern_match("[a-]", "-"));
+        assert!(pattern_match("[[:digit:][:upper:]]*", "Q1"));
+        assert!(!pattern_match("[[:space:]]", "x"));
+        // An unclosed bracket is a literal '['.
+        assert!(pattern_match("[ab", "[ab"));
+    }
+
+    #[test]
+    fn escaped_characters_are_literal() {
+        assert!(pattern_match("\\*", "*"));
+        assert!(!pattern_match("\\*", "x"));
+        assert!(pattern_match("[\\]]", "]"));
+        assert!(has_glob("a*"));
+        assert!(!has_glob("a\\*\\?\\["));
+        assert_eq!(unescape("a\\*b\\\\"), "a*b\\");
+    }
+
+    #[test]
+    fn glob_lists_matching_paths() {
+        let directory = std::env::temp_dir().join(format!("glob-{}", std::process::id()));
+        for path in ["b.rs", "a.rs", ".hidden.rs", "sub/c.rs", "a.txt"] {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        let directory = directory.to_str().unwrap().to_string();
+        let paths = |pattern: &str| -> Vec<String> {
+            glob(&format!("{}/{}", directory, pattern))
+                .iter()
+                .map(|path| path[directory.len() + 1..].to_string())
+                .collect()
+        };
+        assert_eq!(paths("*.rs"), ["a.rs", "b.rs"]);
+        assert_eq!(paths(".*.rs"), [".hidden.rs"]);
+        assert_eq!(paths("*/*.rs"), ["sub/c.rs"]);
+        assert_eq!(paths("*/"), ["sub/"]);
+        assert_eq!(paths("a.\\*"), Vec::<String>::new());
+        assert!(paths("*.md").is_empty());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
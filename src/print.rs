@@ -0,0 +1,242 @@
+use crate::ast::*;
+use crate::parser::unquote;
+
+// Turns a syntax tree back into source text, the way `declare -f` and `type`
+// show the definition of a function. Words are printed as they were written.
+
+const INDENT: &str = "    ";
+
+fn redirect(redirect: &Redirect) -> String {
+    let (op, default_fd) = match redirect.op {
+        RedirectOp::Read => ("<", 0),
+        RedirectOp::Write => (">", 1),
+        RedirectOp::Clobber => (">|", 1),
+        RedirectOp::Append => (">>", 1),
+        RedirectOp::ReadWrite => ("<>", 0),
+        RedirectOp::DupRead => ("<&", 0),
+        RedirectOp::DupWrite => (">&", 1),
+        RedirectOp::HereString => ("<<<", 0),
+        RedirectOp::HereDocument => ("<<", 0),
+    };
+    let fd = if redirect.fd == default_fd {
+        String::new()
+    } else {
+        redirect.fd.to_string()
+    };
+    if let Some(document) = &redirect.document {
+        return format!(
+            "{}{}{}\n{}{}",
+            fd,
+            op,
+            redirect.target,
+            document,
+            unquote(&redirect.target)
+        );
+    }
+    match redirect.op {
+        RedirectOp::DupRead | RedirectOp::DupWrite => format!("{}{}{}", fd, op, redirect.target),
+        _ => format!("{}{} {}", fd, op, redirect.target),
+    }
+}
+
+fn redirects(out: &mut String, redirects: &[Redirect]) {
+    for r in redirects {
+        out.push(' ');
+        out.push_str(&redirect(r));
+    }
+}
+
+fn simple_command(command: &SimpleCommand) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    for assignment in &command.assignments {
+        parts.push(format!("{}={}", assignment.name, assignment.value));
+    }
+    parts.extend(command.words.iter().cloned());
+    let mut out = parts.join(" ");
+    redirects(&mut out, &command.redirects);
+    out.trim_start().to_string()
+}
+
+// Prints the items of a list one per line, each at the given depth.
+fn list(out: &mut String, list: &List, depth: usize) {
+    for item in &list.items {
+        out.push_str(&INDENT.repeat(depth));
+        out.push_str(&and_or(&item.and_or, depth));
+        if item.background {
+            out.push_str(" &");
+        }
+        out.push('\n');
+    }
+}
+
+// Prints a list on a single line, as found after `if` or `while`.
+fn inline_list(list: &List, depth: usize) -> String {
+    let mut out = String::new();
+    for (i, item) in list.items.iter().enumerate() {
+        out.push_str(&and_or(&item.and_or, depth));
+        if item.background {
+            out.push_str(" &");
+        } else if i + 1 < list.items.len() {
+            out.push(';');
+        }
+        if i + 1 < list.items.len() {
+            out.push(' ');
+        }
+    }
+    out
+}
+
+fn and_or(and_or: &AndOr, depth: usize) -> String {
+    let mut out = pipeline(&and_or.first, depth);
+    for (connector, next) in &and_or.rest {
+        out.push_str(match connector {
+            Connector::And => " && ",
+            Connector::Or => " || ",
+        });
+        out.push_str(&pipeline(next, depth));
+    }
+    out
+}
+
+fn pipeline(pipeline: &Pipeline, depth: usize) -> String {
+    let commands: Vec<String> = pipeline
+        .commands
+        .iter()
+        .map(|c| command(c, depth))
+        .collect();
+    let out = commands.join(" | ");
+    if pipeline.negated {
+        format!("! {}", out)
+    } else {
+        out
+    }
+}
+
+// Prints a command whose first line starts at the current position and whose
+// other lines are indented to the given depth.
+fn command(command: &Command, depth: usize) -> String {
+    match command {
+        Command::Simple(simple) => simple_command(simple),
+        Command::Compound(compound, list) => {
+            let mut out = compound_command(compound, depth);
+            redirects(&mut out, list);
+            out
+        }
+        Command::FunctionDef(name, body) => function(name, body, depth),
+    }
+}
+
+// Bodies other than a brace group are printed inside one, as bash does.
+fn function(name: &str, body: &Command, depth: usize) -> String {
+    let indent = INDENT.repeat(depth);
+    match body {
+        Command::Compound(CompoundCommand::BraceGroup(_), _) => {
+            format!("{} ()\n{}{}", name, indent, command(body, depth))
+        }
+        _ => format!(
+            "{} ()\n{}{{\n{}{}{}\n{}}}",
+            name,
+            indent,
+            indent,
+            INDENT,
+            command(body, depth + 1),
+            indent
+        ),
+    }
+}
+
+fn conditional(expression: &CondExpr) -> String {
+    match expression {
+        CondExpr::Word(word) => word.clone(),
+        CondExpr::Unary(op, operand) => format!("{} {}", op, operand),
+        CondExpr::Binary(left, op, right) => format!("{} {} {}", left, op, right),
+        CondExpr::Not(expression) => format!("! {}", conditional(expression)),
+        CondExpr::And(left, right) => format!("{} && {}", conditional(left), conditional(right)),
+        CondExpr::Or(left, right) => format!("{} || {}", conditional(left), conditional(right)),
+        CondExpr::Group(expression) => format!("( {} )", conditional(expression)),
+    }
+}
+
+fn compound_command(compound: &CompoundCommand, depth: usize) -> String {
+    let indent = INDENT.repeat(depth);
+    let mut out = String::new();
+    match compound {
+        CompoundCommand::BraceGroup(body) => {
+            out.push_str("{\n");
+            list(&mut out, body, depth + 1);
+            out.push_str(&indent);
+            out.push('}');
+        }
+        CompoundCommand::Subshell(body) => {
+            out.push_str(&format!("( {} )", inline_list(body, depth)));
+        }
+        CompoundCommand::Conditional(expression) => {
+            out.push_str(&format!("[[ {} ]]", conditional(expression)));
+        }
+        CompoundCommand::If(clause) => {
+            for (i, (condition, body)) in clause.branches.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(&indent);
+                    out.push_str("el");
+                }
+                out.push_str(&format!("if {}; then\n", inline_list(condition, depth)));
+                list(&mut out, body, depth + 1);
+            }
+            if let Some(otherwise) = &clause.otherwise {
+                out.push_str(&indent);
+                out.push_str("else\n");
+                list(&mut out, otherwise, depth + 1);
+            }
+            out.push_str(&indent);
+            out.push_str("fi");
+        }
+        CompoundCommand::While(clause) => {
+            let keyword = if clause.until { "until" } else { "while" };
+            out.push_str(&format!(
+                "{} {}; do\n",
+                keyword,
+                inline_list(&clause.condition, depth)
+            ));
+            list(&mut out, &clause.body, depth + 1);
+            out.push_str(&indent);
+            out.push_str("done");
+        }
+        CompoundCommand::For(clause) => {
+            out.push_str(&format!("for {}", clause.name));
+            if let Some(words) = &clause.words {
+                out.push_str(" in");
+                for word in words {
+                    out.push(' ');
+                    out.push_str(word);
+                }
+            }
+            out.push_str("; do\n");
+            list(&mut out, &clause.body, depth + 1);
+            out.push_str(&indent);
+            out.push_str("done");
+        }
+        CompoundCommand::Case(clause) => {
+            out.push_str(&format!("case {} in\n", clause.word));
+            for item in &clause.items {
+                out.push_str(&INDENT.repeat(depth + 1));
+                out.push_str(&item.patterns.join(" | "));
+                out.push_str(")\n");
+                list(&mut out, &item.body, depth + 2);
+                out.push_str(&INDENT.repeat(depth + 2));
+                out.push_str(match item.terminator {
+                    CaseTerminator::Break => ";;\n",
+                    CaseTerminator::FallThrough => ";&\n",
+                    CaseTerminator::Continue => ";;&\n",
+                });
+            }
+            out.push_str(&indent);
+            out.push_str("esac");
+        }
+    }
+    out
+}
+
+// Prints the definition of a function so that it can be read back.
+pub fn function_definition(name: &str, body: &Command) -> String {
+    function(name, body, 0)
+}
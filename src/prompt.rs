@@ -0,0 +1,188 @@
+use std::ffi::CStr;
+
+use crate::expand::expand_prompt;
+use crate::Shell;
+
+// The prompts PS1, PS2 and PS4. Their backslash escapes are decoded first,
+// then, with `shopt -s promptvars`, their parameters and command
+// substitutions are expanded. The text between \[ and \] does not move the
+// cursor; it is kept between \x01 and \x02 so that the width of the prompt
+// can be known.
+
+pub const VERSION: &str = "3.2";
+
+extern "C" {
+    fn strftime(
+        s: *mut libc::c_char,
+        max: libc::size_t,
+        format: *const libc::c_char,
+        tm: *const libc::tm,
+    ) -> libc::size_t;
+}
+
+// Formats the current local time with strftime.
+fn time(format: &str) -> String {
+    let format = match std::ffi::CString::new(format) {
+        Ok(format) => format,
+        Err(_) => return String::new(),
+    };
+    let mut buffer = [0u8; 256];
+    let length = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return String::new();
+    }
+    let end = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..end]).into_owned()
+}
+
+fn user(shell: &Shell) -> String {
+    let passwd = unsafe { libc::getpwuid(libc::geteuid()) };
+    if passwd.is_null() {
+        return shell.env.get("USER").cloned().unwrap_or_default();
+    }
+    unsafe { CStr::from_ptr((*passwd).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn terminal() -> String {
+    let name = unsafe { libc::ttyname(0) };
+    if name.is_null() {
+        return "tty".to_string();
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy();
+    name.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+// The current directory, with the home directory abbreviated to a tilde.
+fn working_directory(shell: &Shell) -> String {
+    let pwd = shell.env.get("PWD").cloned().unwrap_or_default();
+    match shell.env.get("HOME").filter(|home| !home.is_empty()) {
+        Some(home) if pwd == *home => "~".to_string(),
+        Some(home) if pwd.starts_with(&format!("{}/", home.trim_end_matches('/'))) => {
+            format!("~{}", &pwd[home.trim_end_matches('/').len()..])
+        }
+        _ => pwd,
+    }
+}
+
+// Replaces the backslash escapes of a prompt. When the prompt is expanded
+// afterwards, the text they produce is protected from the expansion.
+fn decode(shell: &Shell, text: &str, protect: bool) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut prompt = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            prompt.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let mut push = |value: &str| {
+            for c in value.chars() {
+                if protect && matches!(c, '$' | '`' | '\\') {
+                    prompt.push('\\');
+                }
+                prompt.push(c);
+            }
+        };
+        i += 1;
+        match chars[i] {
+            'a' => push("\x07"),
+            'e' => push("\x1b"),
+            'n' => push("\n"),
+            'r' => push("\r"),
+            '\\' => push("\\"),
+            'd' => push(&time("%a %b %d")),
+            't' => push(&time("%H:%M:%S")),
+            'T' => push(&time("%I:%M:%S")),
+            '@' => push(&time("%I:%M %p")),
+            'A' => push(&time("%H:%M")),
+            'D' if chars.get(i + 1) == Some(&'{') => {
+                let end = match chars[i..].iter().position(|&c| c == '}') {
+                    Some(end) => i + end,
+                    None => {
+                        push("\\D");
+                        i += 1;
+                        continue;
+                    }
+                };
+                let format: String = chars[i + 2..end].iter().collect();
+                push(&time(if format.is_empty() { "%X" } else { &format }));
+                i = end;
+            }
+            'h' => push(hostname().split('.').next().unwrap_or_default()),
+            'H' => push(&hostname()),
+            'j' => push(&shell.jobs.len().to_string()),
+            'l' => push(&terminal()),
+            's' => push(shell.name.rsplit('/').next().unwrap_or_default()),
+            'u' => push(&user(shell)),
+            'v' | 'V' => push(VERSION),
+            'w' => push(&working_directory(shell)),
+            'W' => {
+                let directory = working_directory(shell);
+                match directory.rsplit('/').next() {
+                    Some(base) if !base.is_empty() => push(base),
+                    _ => push(&directory),
+                }
+            }
+            '!' => push(&shell.history.next_number().to_string()),
+            '#' => push(&(shell.command_number + 1).to_string()),
+            '$' if unsafe { libc::geteuid() } == 0 => push("#"),
+            '$' => push("$"),
+            '[' => push("\x01"),
+            ']' => push("\x02"),
+            '0'..='7' => {
+                let digits: String = chars[i..]
+                    .iter()
+                    .take(3)
+                    .take_while(|c| ('0'..='7').contains(c))
+                    .collect();
+                let code = u32::from_str_radix(&digits, 8).unwrap_or(0);
+                push(&char::from_u32(code).unwrap_or('?').to_string());
+                i += digits.len() - 1;
+            }
+            c => {
+                push("\\");
+                push(&c.to_string());
+            }
+        }
+        i += 1;
+    }
+    prompt
+}
+
+// The value of a prompt variable, ready to be shown. Unset, it is empty.
+pub fn prompt(shell: &mut Shell, variable: &str) -> String {
+    let text = match shell.env.get(variable) {
+        Some(text) => text.clone(),
+        None => return String::new(),
+    };
+    let promptvars = shell.options.promptvars;
+    let decoded = decode(shell, &text, promptvars);
+    if promptvars {
+        expand_prompt(shell, &decoded)
+    } else {
+        decoded
+    }
+}
+
+// Removes the markers of the text that does not move the cursor.
+pub fn printable(prompt: &str) -> String {
+    prompt.replace(['\x01', '\x02'], "")
+}
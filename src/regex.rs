@@ -0,0 +1,519 @@
+use std::sync::atomic::Ordering;
+
+use crate::pattern::class_matches;
+use crate::INTERRUPTED;
+
+// POSIX extended regular expressions, for the `=~` operator of `[[ ]]`. The
+// expression is compiled to a program run on all its paths at once, one
+// character at a time, so matching takes time linear in the text. The match
+// is the leftmost one and, from there, the longest, as POSIX requires.
+
+#[derive(Clone)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String), // [:alpha:] and the like
+}
+
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool), // items and whether the class is negated
+    Start,
+    End,
+    Group(Box<Node>, usize), // capture group and its number
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>), // minimum and maximum count
+}
+
+// An instruction of a compiled expression.
+enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    Save(usize),         // records the position in a capture slot
+    Progress(usize),     // fails if the position is still the one saved in the slot
+    Split(usize, usize), // tries both, the first one with priority
+    Jump(usize),
+    Match,
+}
+
+// Beyond this many instructions, bounds such as {1000} are refused.
+const MAX_PROGRAM: usize = 100_000;
+
+pub struct Regex {
+    program: Vec<Inst>,
+    groups: usize,
+    slots: usize,
+}
+
+// The start and end of each group, in characters, in slots 2n and 2n + 1.
+// The first group is the whole match. Slots after the groups record where
+// an optional iteration of a repetition started.
+type Captures = Vec<Option<usize>>;
+
+// A path through the program, waiting at an instruction that reads a
+// character.
+struct Thread {
+    pc: usize,
+    captures: Captures,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternation(branches)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes: Vec<Node> = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantifiers(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        Ok(match c {
+            '(' => {
+                self.groups += 1;
+                let index = self.groups;
+                let node = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err("Unmatched ( or \\(".to_string());
+                }
+                self.pos += 1;
+                Node::Group(Box::new(node), index)
+            }
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '[' => self.bracket()?,
+            '\\' => match self.peek() {
+                Some(c) => {
+                    self.pos += 1;
+                    match c {
+                        'w' => Node::Class(
+                            vec![ClassItem::Named("alnum".into()), ClassItem::Char('_')],
+                            false,
+                        ),
+                        'W' => Node::Class(
+                            vec![ClassItem::Named("alnum".into()), ClassItem::Char('_')],
+                            true,
+                        ),
+                        's' => Node::Class(vec![ClassItem::Named("space".into())], false),
+                        'S' => Node::Class(vec![ClassItem::Named("space".into())], true),
+                        c => Node::Char(c),
+                    }
+                }
+                None => return Err("Trailing backslash".to_string()),
+            },
+            '*' | '+' | '?' => return Err("Invalid preceding regular expression".to_string()),
+            c => Node::Char(c),
+        })
+    }
+
+    // Parses a bound such as {2}, {2,} or {2,5} after the '{' at self.pos.
+    fn bound(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let end = rest.find('}')?;
+        let inside = &rest[..end];
+        let (min, max) = match inside.split_once(',') {
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+            None => (inside.parse().ok()?, Some(inside.parse().ok()?)),
+        };
+        self.pos += inside.chars().count() + 2;
+        Some((min, max))
+    }
+
+    fn quantifiers(&mut self, mut atom: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.bound() {
+                    Some((min, max)) if max.is_none_or(|max| min <= max) => {
+                        atom = Node::Repeat(Box::new(atom), min, max);
+                        continue;
+                    }
+                    Some(_) => return Err("Invalid content of \\{\\}".to_string()),
+                    None => return Ok(atom),
+                },
+                _ => return Ok(atom),
+            };
+            self.pos += 1;
+            atom = Node::Repeat(Box::new(atom), min, max);
+        }
+    }
+
+    fn bracket(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut items: Vec<ClassItem> = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err("Unmatched [, [^, [:, [., or [=".to_string()),
+            };
+            self.pos += 1;
+            if c == ']' && !first {
+                return Ok(Node::Class(items, negated));
+            }
+            first = false;
+            if c == '[' && self.peek() == Some(':') {
+                let rest: String = self.chars[self.pos + 1..].iter().collect();
+                if let Some(end) = rest.find(":]") {
+                    items.push(ClassItem::Named(rest[..end].to_string()));
+                    self.pos += 1 + rest[..end].chars().count() + 2;
+                    continue;
+                }
+            }
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                let high = self.chars[self.pos + 1];
+                self.pos += 2;
+                items.push(ClassItem::Range(c, high));
+            } else {
+                items.push(ClassItem::Char(c));
+            }
+        }
+    }
+}
+
+fn class_contains(items: &[ClassItem], c: char) -> bool {
+    items.iter().any(|item| match item {
+        ClassItem::Char(item) => *item == c,
+        ClassItem::Range(low, high) => *low <= c && c <= *high,
+        ClassItem::Named(class) => class_matches(class, c),
+    })
+}
+
+// Compiles a node, appending its instructions to the program. `slots` is the
+// number of capture slots used so far.
+fn compile(node: &Node, program: &mut Vec<Inst>, slots: &mut usize) -> Result<(), String> {
+    if program.len() > MAX_PROGRAM {
+        return Err("Regular expression too big".to_string());
+    }
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(items, negated) => program.push(Inst::Class(items.clone(), *negated)),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(node, index) => {
+            program.push(Inst::Save(2 * index));
+            compile(node, program, slots)?;
+            program.push(Inst::Save(2 * index + 1));
+        }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program, slots)?;
+            }
+        }
+        Node::Alternation(branches) => {
+            let mut jumps: Vec<usize> = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, program, slots)?;
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(branch, program, slots)?;
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(node, min, max) => {
+            for _ in 0..*min {
+                compile(node, program, slots)?;
+            }
+            // An iteration beyond the minimum must not match the empty
+            // string, so that the repetition ends instead.
+            let slot = *slots;
+            *slots += 1;
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    program.push(Inst::Save(slot));
+                    compile(node, program, slots)?;
+                    program.push(Inst::Progress(slot));
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    // Each optional copy is only tried after the one before.
+                    let mut splits: Vec<usize> = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        program.push(Inst::Save(slot));
+                        compile(node, program, slots)?;
+                        program.push(Inst::Progress(slot));
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+        };
+        let root = parser.alternation()?;
+        if parser.pos < parser.chars.len() {
+            return Err("Unmatched ) or \\)".to_string());
+        }
+        let mut program: Vec<Inst> = Vec::new();
+        let mut slots = 2 * (parser.groups + 1);
+        compile(&root, &mut program, &mut slots)?;
+        program.push(Inst::Match);
+        Ok(Regex {
+            program,
+            groups: parser.groups,
+            slots,
+        })
+    }
+
+    // Follows the path starting at pc through the instructions that read no
+    // character, and queues the threads it leads to, in priority order. An
+    // instruction already reached at this position is not followed again, so
+    // a repetition of the empty string ends there.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<Thread>,
+        visited: &mut [bool],
+        pc: usize,
+        pos: usize,
+        length: usize,
+        captures: Captures,
+    ) {
+        let mut stack = vec![(pc, captures)];
+        while let Some((pc, mut captures)) = stack.pop() {
+            if visited[pc] {
+                continue;
+            }
+            visited[pc] = true;
+            match self.program[pc] {
+                Inst::Jump(target) => stack.push((target, captures)),
+                Inst::Split(first, second) => {
+                    stack.push((second, captures.clone()));
+                    stack.push((first, captures));
+                }
+                Inst::Save(slot) => {
+                    captures[slot] = Some(pos);
+                    stack.push((pc + 1, captures));
+                }
+                Inst::Progress(slot) if captures[slot] != Some(pos) => {
+                    stack.push((pc + 1, captures))
+                }
+                Inst::Start if pos == 0 => stack.push((pc + 1, captures)),
+                Inst::End if pos == length => stack.push((pc + 1, captures)),
+                Inst::Progress(_) | Inst::Start | Inst::End => {}
+                _ => threads.push(Thread { pc, captures }),
+            }
+        }
+    }
+
+    // Returns the substrings matched by the whole expression and by each
+    // group, or None when the text does not match. Groups that did not take
+    // part in the match are empty.
+    pub fn captures(&self, text: &str) -> Option<Vec<String>> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut threads: Vec<Thread> = Vec::new();
+        let mut best: Option<Captures> = None;
+
+        for pos in 0..=chars.len() {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return None;
+            }
+            let mut visited = vec![false; self.program.len()];
+            let mut next: Vec<Thread> = Vec::new();
+            for thread in threads {
+                self.add_thread(
+                    &mut next,
+                    &mut visited,
+                    thread.pc,
+                    pos,
+                    chars.len(),
+                    thread.captures,
+                );
+            }
+            // Matches starting further right only count while none was found.
+            if best.is_none() {
+                let mut captures = vec![None; self.slots];
+                captures[0] = Some(pos);
+                self.add_thread(&mut next, &mut visited, 0, pos, chars.len(), captures);
+            }
+
+            threads = Vec::new();
+            for thread in next {
+                let start = thread.captures[0];
+                let c = chars.get(pos);
+                let advance = match &self.program[thread.pc] {
+                    Inst::Char(expected) => c == Some(expected),
+                    Inst::Any => c.is_some(),
+                    Inst::Class(items, negated) => {
+                        c.is_some_and(|&c| class_contains(items, c) != *negated)
+                    }
+                    Inst::Match => {
+                        // The first thread to get here has priority, but a
+                        // match starting further left or ending later wins.
+                        let better = match &best {
+                            None => true,
+                            Some(best) => {
+                                start < best[0] || (start == best[0] && Some(pos) > best[1])
+                            }
+                        };
+                        if better {
+                            let mut captures = thread.captures.clone();
+                            captures[1] = Some(pos);
+                            best = Some(captures);
+                        }
+                        false
+                    }
+                    _ => false,
+                };
+                if advance && best.as_ref().is_none_or(|best| start <= best[0]) {
+                    threads.push(Thread {
+                        pc: thread.pc + 1,
+                        captures: thread.captures,
+                    });
+                }
+            }
+            if threads.is_empty() && best.is_some() {
+                break;
+            }
+        }
+
+        let captures = best?;
+        Some(
+            (0..=self.groups)
+                .map(
+                    |group| match (captures[2 * group], captures[2 * group + 1]) {
+                        (Some(start), Some(end)) if start <= end => {
+                            chars[start..end].iter().collect()
+                        }
+                        _ => String::new(),
+                    },
+                )
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captures(pattern: &str, text: &str) -> Option<Vec<String>> {
+        Regex::new(pattern).unwrap().captures(text)
+    }
+
+    #[test]
+    fn nested_repetitions_do_not_backtrack() {
+        let text = format!("{}b", "a".repeat(25));
+        assert_eq!(captures("(a*)*c", &text), None);
+        let text = format!("{}b", "a".repeat(30));
+        assert_eq!(captures("(a|aa)+c", &text), None);
+        let text = "a".repeat(5000);
+        assert_eq!(captures("(a|aa)+$", &text).unwrap()[0], text);
+    }
+
+    #[test]
+    fn leftmost_then_longest() {
+        assert_eq!(captures("(a|aa)+", "xaaa").unwrap(), ["aaa", "a"]);
+        assert_eq!(captures("a{2,3}", "aaaa").unwrap(), ["aaa"]);
+        assert_eq!(captures("x*", "abc").unwrap(), [""]);
+        assert_eq!(captures("b|abc", "abcd").unwrap(), ["abc"]);
+    }
+
+    #[test]
+    fn groups_fill_bash_rematch() {
+        assert_eq!(
+            captures("([a-z]+)-([0-9]{2})", "id: abc-42!").unwrap(),
+            ["abc-42", "abc", "42"]
+        );
+        assert_eq!(
+            captures("(a|ab)(c|bcd)(d*)", "abcd").unwrap(),
+            ["abcd", "a", "bcd", ""]
+        );
+        assert_eq!(captures("(foo)?bar", "bar").unwrap(), ["bar", ""]);
+        assert_eq!(captures("(a*)+", "b").unwrap(), ["", ""]);
+        // An optional iteration matching nothing does not replace the group.
+        assert_eq!(captures("(b|){1,2}(a|b)", "bab").unwrap(), ["ba", "b", "a"]);
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(captures("^ab", "cab"), None);
+        assert_eq!(captures("b$", "abc"), None);
+        assert_eq!(captures("^(x|y)+$", "xyyx").unwrap(), ["xyyx", "x"]);
+        assert_eq!(captures("^$", "").unwrap(), [""]);
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert_eq!(captures("[[:digit:]]+", "ab123c").unwrap(), ["123"]);
+        assert_eq!(captures("[^a-c]+", "abcdefa").unwrap(), ["def"]);
+        assert_eq!(captures("[]a]+", "x]a]b").unwrap(), ["]a]"]);
+        assert_eq!(captures("[a-]+", "x-a-").unwrap(), ["-a-"]);
+        assert_eq!(captures("\\.", "a.b").unwrap(), ["."]);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(Regex::new("(a").is_err());
+        assert!(Regex::new("a)").is_err());
+        assert!(Regex::new("[a").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("a{3,2}").is_err());
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+    }
+}
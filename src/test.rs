@@ -0,0 +1,355 @@
+use std::ffi::CString;
+
+use crate::Shell;
+
+// The `test` and `[` builtins. Expressions of up to four arguments follow the
+// POSIX rules, which decide from the number of arguments what each one is;
+// longer ones are parsed with `!`, `-a`, `-o` and parentheses.
+
+fn stat(path: &str, follow: bool) -> Option<libc::stat> {
+    let path = CString::new(path).ok()?;
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        if follow {
+            libc::stat(path.as_ptr(), &mut stat)
+        } else {
+            libc::lstat(path.as_ptr(), &mut stat)
+        }
+    };
+    (result == 0).then_some(stat)
+}
+
+fn access(path: &str, mode: i32) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn file_type(path: &str, kind: libc::mode_t) -> bool {
+    stat(path, true).is_some_and(|stat| stat.st_mode & libc::S_IFMT == kind)
+}
+
+fn mode_bit(path: &str, bit: libc::mode_t) -> bool {
+    stat(path, true).is_some_and(|stat| stat.st_mode & bit != 0)
+}
+
+pub fn is_unary(op: &str) -> bool {
+    matches!(
+        op,
+        "-b" | "-c"
+            | "-d"
+            | "-e"
+            | "-f"
+            | "-g"
+            | "-h"
+            | "-k"
+            | "-L"
+            | "-n"
+            | "-N"
+            | "-O"
+            | "-G"
+            | "-p"
+            | "-r"
+            | "-s"
+            | "-S"
+            | "-t"
+            | "-u"
+            | "-v"
+            | "-w"
+            | "-x"
+            | "-z"
+    )
+}
+
+pub fn is_binary(op: &str) -> bool {
+    matches!(
+        op,
+        "=" | "=="
+            | "!="
+            | "<"
+            | ">"
+            | "-eq"
+            | "-ne"
+            | "-lt"
+            | "-le"
+            | "-gt"
+            | "-ge"
+            | "-nt"
+            | "-ot"
+            | "-ef"
+    )
+}
+
+pub fn unary(shell: &Shell, op: &str, arg: &str) -> bool {
+    match op {
+        "-b" => file_type(arg, libc::S_IFBLK),
+        "-c" => file_type(arg, libc::S_IFCHR),
+        "-d" => file_type(arg, libc::S_IFDIR),
+        "-e" => stat(arg, true).is_some(),
+        "-f" => file_type(arg, libc::S_IFREG),
+        "-g" => mode_bit(arg, libc::S_ISGID),
+        "-h" | "-L" => {
+            stat(arg, false).is_some_and(|stat| stat.st_mode & libc::S_IFMT == libc::S_IFLNK)
+        }
+        "-k" => mode_bit(arg, libc::S_ISVTX),
+        "-n" => !arg.is_empty(),
+        "-N" => stat(arg, true).is_some_and(|stat| stat.st_mtime > stat.st_atime),
+        "-O" => stat(arg, true).is_some_and(|stat| stat.st_uid == unsafe { libc::geteuid() }),
+        "-G" => stat(arg, true).is_some_and(|stat| stat.st_gid == unsafe { libc::getegid() }),
+        "-p" => file_type(arg, libc::S_IFIFO),
+        "-r" => access(arg, libc::R_OK),
+        "-s" => stat(arg, true).is_some_and(|stat| stat.st_size > 0),
+        "-S" => file_type(arg, libc::S_IFSOCK),
+        "-t" => arg
+            .trim()
+            .parse::<i32>()
+            .is_ok_and(|fd| unsafe { libc::isatty(fd) } == 1),
+        "-u" => mode_bit(arg, libc::S_ISUID),
+        "-v" => shell.env.get(arg).is_some(),
+        "-w" => access(arg, libc::W_OK),
+        "-x" => access(arg, libc::X_OK),
+        _ => arg.is_empty(), // -z
+    }
+}
+
+pub fn is_integer_operator(op: &str) -> bool {
+    matches!(op, "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge")
+}
+
+pub fn compare_integers(left: i64, op: &str, right: i64) -> bool {
+    match op {
+        "-eq" => left == right,
+        "-ne" => left != right,
+        "-lt" => left < right,
+        "-le" => left <= right,
+        "-gt" => left > right,
+        _ => left >= right, // -ge
+    }
+}
+
+fn integer(arg: &str) -> Result<i64, String> {
+    arg.trim()
+        .parse::<i64>()
+        .map_err(|_| format!("{}: integer expression expected", arg))
+}
+
+fn modified(path: &str) -> Option<(i64, i64)> {
+    stat(path, true).map(|stat| (stat.st_mtime, stat.st_mtime_nsec))
+}
+
+pub fn binary(left: &str, op: &str, right: &str) -> Result<bool, String> {
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        op if is_integer_operator(op) => compare_integers(integer(left)?, op, integer(right)?),
+        "-nt" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left > right,
+            (left, _) => left.is_some(),
+        },
+        "-ot" => match (modified(left), modified(right)) {
+            (Some(left), Some(right)) => left < right,
+            (_, right) => right.is_some(),
+        },
+        _ => match (stat(left, true), stat(right, true)) {
+            (Some(left), Some(right)) => left.st_dev == right.st_dev && left.st_ino == right.st_ino,
+            _ => false,
+        }, // -ef
+    })
+}
+
+struct Parser<'a> {
+    shell: &'a Shell,
+    args: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn arg(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).map(|arg| arg.as_str())
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut result = self.and()?;
+        while self.arg(0) == Some("-o") {
+            self.pos += 1;
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut result = self.term()?;
+        while self.arg(0) == Some("-a") {
+            self.pos += 1;
+            result &= self.term()?;
+        }
+        Ok(result)
+    }
+
+    fn term(&mut self) -> Result<bool, String> {
+        let arg = match self.arg(0) {
+            Some(arg) => arg,
+            None => return Err("argument expected".to_string()),
+        };
+        if arg == "!" {
+            self.pos += 1;
+            return Ok(!self.term()?);
+        }
+        if arg == "(" {
+            self.pos += 1;
+            let result = self.or()?;
+            if self.arg(0) != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            self.pos += 1;
+            return Ok(result);
+        }
+        if let (Some(op), Some(right)) = (self.arg(1), self.arg(2)) {
+            if is_binary(op) {
+                self.pos += 3;
+                return binary(arg, op, right);
+            }
+        }
+        if is_unary(arg) {
+            return match self.arg(1) {
+                Some(operand) => {
+                    self.pos += 2;
+                    Ok(unary(self.shell, arg, operand))
+                }
+                None => Err("argument expected".to_string()),
+            };
+        }
+        self.pos += 1;
+        Ok(!arg.is_empty())
+    }
+}
+
+fn evaluate(shell: &Shell, args: &[String]) -> Result<bool, String> {
+    let arg = |i: usize| args[i].as_str();
+    match args.len() {
+        0 => return Ok(false),
+        1 => return Ok(!args[0].is_empty()),
+        2 if arg(0) == "!" => return Ok(args[1].is_empty()),
+        2 if is_unary(arg(0)) => return Ok(unary(shell, arg(0), arg(1))),
+        2 => return Err(format!("{}: unary operator expected", arg(0))),
+        3 if is_binary(arg(1)) => return binary(arg(0), arg(1), arg(2)),
+        3 if arg(1) == "-a" => return Ok(!args[0].is_empty() && !args[2].is_empty()),
+        3 if arg(1) == "-o" => return Ok(!args[0].is_empty() || !args[2].is_empty()),
+        3 if arg(0) == "!" => return Ok(!evaluate(shell, &args[1..])?),
+        3 if arg(0) == "(" && arg(2) == ")" => return Ok(!args[1].is_empty()),
+        3 => return Err(format!("{}: binary operator expected", arg(1))),
+        4 if arg(0) == "!" => return Ok(!evaluate(shell, &args[1..])?),
+        4 if arg(0) == "(" && arg(3) == ")" => return evaluate(shell, &args[1..3]),
+        _ => {}
+    }
+    let mut parser = Parser {
+        shell,
+        args,
+        pos: 0,
+    };
+    let result = parser.or()?;
+    match parser.arg(0) {
+        None => Ok(result),
+        Some(_) => Err("too many arguments".to_string()),
+    }
+}
+
+pub fn test_handler(shell: &Shell, name: &str, args: &[String]) -> i32 {
+    let args = if name == "[" {
+        match args.split_last() {
+            Some((last, args)) if last == "]" => args,
+            _ => {
+                eprintln!("minibash: [: missing `]'");
+                return 2;
+            }
+        }
+    } else {
+        args
+    };
+    match evaluate(shell, args) {
+        Ok(result) => !result as i32,
+        Err(message) => {
+            eprintln!("minibash: {}: {}", name, message);
+            2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(args: &[&str]) -> Result<bool, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        evaluate(&Shell::default(), &args)
+    }
+
+    #[test]
+    fn by_number_of_arguments() {
+        assert_eq!(test(&[]), Ok(false));
+        assert_eq!(test(&[""]), Ok(false));
+        assert_eq!(test(&["-z"]), Ok(true));
+        assert_eq!(test(&["!", ""]), Ok(true));
+        assert_eq!(test(&["-n", ""]), Ok(false));
+        assert_eq!(test(&["-a", "-o", ""]), Ok(true));
+        assert_eq!(test(&["(", "", ")"]), Ok(false));
+        assert_eq!(test(&["!", "a", "=", "a"]), Ok(false));
+        assert_eq!(test(&["(", "!", "", ")"]), Ok(true));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(test(&["abc", "==", "abc"]), Ok(true));
+        assert_eq!(test(&["a", "<", "b"]), Ok(true));
+        assert_eq!(test(&[" 10 ", "-gt", "9"]), Ok(true));
+        assert_eq!(test(&["-3", "-le", "-3"]), Ok(true));
+        assert_eq!(
+            test(&["x", "-eq", "1"]),
+            Err("x: integer expression expected".to_string())
+        );
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(test(&["-d", "/"]), Ok(true));
+        assert_eq!(test(&["-f", "/"]), Ok(false));
+        assert_eq!(test(&["-e", "/no/such/file"]), Ok(false));
+        assert_eq!(test(&["/", "-ef", "/."]), Ok(true));
+        assert_eq!(test(&["/", "-nt", "/no/such/file"]), Ok(true));
+    }
+
+    #[test]
+    fn longer_expressions() {
+        assert_eq!(test(&["a", "-a", "", "-o", "b"]), Ok(true));
+        assert_eq!(test(&["a", "-o", "", "-a", ""]), Ok(true));
+        assert_eq!(test(&["!", "(", "a", "-o", "", ")", "-o", ""]), Ok(false));
+        assert_eq!(test(&["(", "a", "=", "a", ")", "-a", "-n", "x"]), Ok(true));
+        assert_eq!(
+            test(&["(", "a", "-a", "b"]),
+            Err("`)' expected".to_string())
+        );
+        assert_eq!(
+            test(&["a", "b", "c", "d", "e"]),
+            Err("too many arguments".to_string())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            test(&["a", "b"]),
+            Err("a: unary operator expected".to_string())
+        );
+        assert_eq!(
+            test(&["a", "b", "c"]),
+            Err("b: binary operator expected".to_string())
+        );
+        assert_eq!(
+            test(&["a", "-a", "b", "-a"]),
+            Err("argument expected".to_string())
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+// Shell variables live in a stack of scopes. The first one holds the global
+// variables, each function call pushes one for its locals, and a lookup goes
+// from the innermost scope outwards, so that callees see the locals of their
+// callers, as with bash's dynamic scoping.
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Scalar(String),
+    Array(Vec<String>), // indexed array, whose first element is its value
+}
+
+impl Value {
+    pub fn scalar(&self) -> Option<&String> {
+        match self {
+            Value::Scalar(value) => Some(value),
+            Value::Array(elements) => elements.first(),
+        }
+    }
+
+    // The value written the way `declare` and `set` show it.
+    pub fn declaration(&self) -> String {
+        match self {
+            Value::Scalar(value) => format!("\"{}\"", value),
+            Value::Array(elements) => {
+                let elements: Vec<String> = elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| format!("[{}]=\"{}\"", i, element))
+                    .collect();
+                format!("({})", elements.join(" "))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Variable {
+    pub value: Option<Value>, // None when declared without a value or unset
+    pub exported: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Variables {
+    scopes: Vec<HashMap<String, Variable>>,
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        Variables {
+            scopes: vec![HashMap::new()],
+        }
+    }
+}
+
+impl Variables {
+    fn find(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn find_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.find(name)?.value.as_ref()?.scalar()
+    }
+
+    // The elements of an array, or the value of a scalar as its only element.
+    pub fn elements(&self, name: &str) -> Vec<String> {
+        match self.find(name).and_then(|variable| variable.value.as_ref()) {
+            Some(Value::Array(elements)) => elements.clone(),
+            Some(Value::Scalar(value)) => vec![value.clone()],
+            None => Vec::new(),
+        }
+    }
+
+    // Assigns an array in the innermost scope that has the variable, or
+    // globally.
+    pub fn set_array(&mut self, name: &str, elements: Vec<String>) {
+        match self.find_mut(name) {
+            Some(variable) => variable.value = Some(Value::Array(elements)),
+            None => {
+                self.scopes[0].insert(
+                    name.to_string(),
+                    Variable {
+                        value: Some(Value::Array(elements)),
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    // Assigns a variable in the innermost scope that has it, or globally.
+    // Assigning to an array sets its first element.
+    pub fn insert(&mut self, name: String, value: String) {
+        match self.find_mut(&name) {
+            Some(Variable {
+                value: Some(Value::Array(elements)),
+                ..
+            }) if !elements.is_empty() => elements[0] = value,
+            Some(variable) => variable.value = Some(Value::Scalar(value)),
+            None => {
+                self.scopes[0].insert(
+                    name,
+                    Variable {
+                        value: Some(Value::Scalar(value)),
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    // Unsets a variable. A local of the current function stays local, while
+    // unsetting the local of a caller uncovers the variable it was hiding.
+    pub fn remove(&mut self, name: &str) {
+        let depth = self.scopes.len() - 1;
+        let index = match (0..=depth)
+            .rev()
+            .find(|&i| self.scopes[i].contains_key(name))
+        {
+            Some(index) => index,
+            None => return,
+        };
+        if index == depth && index > 0 {
+            if let Some(variable) = self.scopes[index].get_mut(name) {
+                variable.exported = false;
+                variable.value = None;
+            }
+        } else {
+            self.scopes[index].remove(name);
+        }
+    }
+
+    // Gives a value to a global variable, even when a local hides it.
+    pub fn set_global(&mut self, name: &str, value: Option<String>) {
+        let variable = self.scopes[0].entry(name.to_string()).or_default();
+        if let Some(value) = value {
+            variable.value = Some(Value::Scalar(value));
+        }
+    }
+
+    // Marks a variable for export to the commands run, giving it a value when
+    // one is provided.
+    pub fn export(&mut self, name: &str, value: Option<String>) {
+        match self.find_mut(name) {
+            Some(variable) => {
+                variable.exported = true;
+                if let Some(value) = value {
+                    variable.value = Some(Value::Scalar(value));
+                }
+            }
+            None => {
+                self.scopes[0].insert(
+                    name.to_string(),
+                    Variable {
+                        value: value.map(Value::Scalar),
+                        exported: true,
+                    },
+                );
+            }
+        }
+    }
+
+    fn is_exported(&self, name: &str) -> bool {
+        self.find(name).is_some_and(|variable| variable.exported)
+    }
+
+    // Declares a variable in the innermost scope. A local hiding an exported
+    // variable is exported too.
+    pub fn local(&mut self, name: &str, value: Option<String>) {
+        let exported = self.is_exported(name);
+        let value = value.map(Value::Scalar);
+        let scope = self.scopes.last_mut().unwrap();
+        match scope.get_mut(name) {
+            Some(variable) => {
+                if value.is_some() {
+                    variable.value = value;
+                }
+            }
+            None => {
+                scope.insert(name.to_string(), Variable { value, exported });
+            }
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    // The variables of the innermost scope, sorted by name.
+    pub fn locals(&self) -> Vec<(&String, &Variable)> {
+        let mut locals: Vec<_> = self.scopes.last().unwrap().iter().collect();
+        locals.sort_by_key(|a| a.0);
+        locals
+    }
+
+    // Every visible variable, sorted by name, including the ones declared
+    // without a value.
+    pub fn visible(&self) -> Vec<(&String, &Variable)> {
+        let mut visible: HashMap<&String, &Variable> = HashMap::new();
+        for scope in &self.scopes {
+            visible.extend(scope.iter());
+        }
+        let mut sorted: Vec<_> = visible.into_iter().collect();
+        sorted.sort_by_key(|a| a.0);
+        sorted
+    }
+
+    // The variables set and visible, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.visible()
+            .into_iter()
+            .filter_map(|(name, variable)| Some((name, variable.value.as_ref()?)))
+    }
+
+    // The environment given to the commands run. Arrays cannot be exported.
+    pub fn exported(&self) -> Vec<(&String, &String)> {
+        self.visible()
+            .into_iter()
+            .filter(|(_, variable)| variable.exported)
+            .filter_map(|(name, variable)| match &variable.value {
+                Some(Value::Scalar(value)) => Some((name, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}